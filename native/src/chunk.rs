@@ -0,0 +1,16 @@
+// browsers refuse messages from the native host that are larger than this
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+// splits items into parts that each fit, halving any part that doesn't
+// a single item that doesn't fit on its own is still returned, since there's no smaller way to send it
+pub fn split<T>(mut items: Vec<T>, fits: &impl Fn(&[T]) -> bool) -> Vec<Vec<T>> {
+  if items.len() <= 1 || fits(&items) {
+    return vec![items]
+  }
+
+  let second_half = items.split_off(items.len() / 2);
+  let mut parts = split(items, fits);
+  parts.extend(split(second_half, fits));
+
+  parts
+}
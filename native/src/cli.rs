@@ -0,0 +1,354 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use crate::extension;
+use crate::extension::Browser;
+use crate::extension::Scope;
+use crate::platform::Native;
+use crate::platform::Platform;
+use archive::config::Config;
+use archive::config::CONFIG_PATH;
+use archive::index::{Index, Progress};
+
+pub const USAGE: &str = "Usage: archive [COMMAND] [OPTIONS]
+
+Commands:
+  install     Install the native messaging host
+  uninstall   Uninstall the native messaging host
+  status      Show where the native messaging host is installed
+  allow       Add the given extension and add-on IDs to the installed manifests
+  disallow    Remove the given extension and add-on IDs from the installed manifests
+  doctor      Check every manifest location and ping the installed host
+  index       Hash new files in every configured archive directory, showing progress
+  host        Run the native messaging host over stdin/stdout
+  help        Show this message
+
+Running without a command installs the native messaging host if it isn't installed anywhere, and uninstalls it otherwise.
+
+Options:
+  --browser <BROWSER>       Only act on this browser, may be repeated (chrome, chromium, brave, vivaldi, firefox)
+  --extension-id <ID>       Chrome extension ID to allow, may be repeated
+  --addon-id <ID>           Firefox add-on ID to allow, may be repeated
+  --config <FILE>           Read and write settings in this file instead of the default location
+  --manifest-dir <DIR>      Write the manifest into this directory instead of next to the executable (Windows only)
+  --user                    Only act on the installation for the current user (default when installing)
+  --system                  Only act on the installation for all users, which needs to be run as root or an administrator
+
+IDs given on the command line are allowed in addition to the extension_ids and addon_ids listed in the config file, which default to fdnmnpnjacfjphfmhlfgjpmkimbekmnd and archive@dagwaging.com. allow and disallow also update the config file, so later installs keep the change.";
+
+#[derive(Default)]
+pub struct Options {
+  // empty means every browser relevant to the command
+  pub browsers: Vec<Browser>,
+  pub extension_ids: Vec<String>,
+  pub addon_ids: Vec<String>,
+  pub config_path: Option<PathBuf>,
+  pub manifest_directory: Option<PathBuf>,
+  // None means the user installation when changing anything, and both when checking
+  pub scope: Option<Scope>,
+}
+
+pub enum Command {
+  // no arguments, i.e. the executable was double-clicked
+  Toggle,
+  Install(Options),
+  Uninstall(Options),
+  Status(Options),
+  Allow(Options),
+  Disallow(Options),
+  Doctor(Options),
+  Index(Options),
+  Host,
+  Help,
+}
+
+// browsers launch the host with the extension origin (chromium) or the manifest path (firefox) as the first argument
+fn is_browser_invocation(arg: &str) -> bool {
+  arg.starts_with("chrome-extension://") || arg.ends_with(".json")
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+  let (command, rest) = match args.split_first() {
+    None => return Ok(Command::Toggle),
+    Some((command, rest)) => (command.as_str(), rest),
+  };
+
+  if is_browser_invocation(command) {
+    return Ok(Command::Host);
+  }
+
+  let mut options = Options::default();
+  let mut rest = rest.iter();
+
+  while let Some(arg) = rest.next() {
+    let mut value = |name: &str| rest.next().cloned().ok_or_else(|| format!("Missing value for {}", name));
+
+    match arg.as_str() {
+      "--browser" => options.browsers.push(value(arg)?.parse()?),
+      "--extension-id" => options.extension_ids.push(value(arg)?),
+      "--addon-id" => options.addon_ids.push(value(arg)?),
+      "--config" => options.config_path = Some(PathBuf::from(value(arg)?)),
+      "--manifest-dir" => options.manifest_directory = Some(PathBuf::from(value(arg)?)),
+      "--user" => options.scope = Some(Scope::User),
+      "--system" => options.scope = Some(Scope::System),
+      _ => return Err(format!("Unexpected argument '{}'", arg)),
+    }
+  }
+
+  match command {
+    "install" => Ok(Command::Install(options)),
+    "uninstall" => Ok(Command::Uninstall(options)),
+    "status" => Ok(Command::Status(options)),
+    "allow" => Ok(Command::Allow(options)),
+    "disallow" => Ok(Command::Disallow(options)),
+    "doctor" => Ok(Command::Doctor(options)),
+    "index" => Ok(Command::Index(options)),
+    "host" => Ok(Command::Host),
+    "help" | "--help" | "-h" => Ok(Command::Help),
+    _ => Err(format!("Unknown command '{}'", command)),
+  }
+}
+
+fn browser_list(browsers: &[Browser]) -> String {
+  browsers.iter().map(|browser| browser.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn scope(options: &Options) -> Scope {
+  options.scope.unwrap_or(Scope::User)
+}
+
+pub fn scopes(options: &Options) -> Vec<Scope> {
+  options.scope.map_or(Scope::ALL.to_vec(), |scope| vec![scope])
+}
+
+fn describe_error(err: io::Error, scope: Scope) -> String {
+  match (err.kind(), scope) {
+    (io::ErrorKind::PermissionDenied, Scope::System) => format!("{}\n\nSystem-wide changes need to be run as root or an administrator", err),
+    _ => err.to_string()
+  }
+}
+
+fn installed_for(scope: Scope) -> &'static str {
+  match scope {
+    Scope::User => "",
+    Scope::System => " system-wide",
+  }
+}
+
+pub fn config_path(options: &Options) -> Result<PathBuf, String> {
+  options.config_path.clone().map_or_else(||
+    Native::config_directory().map(|directory| directory.join(CONFIG_PATH)).map_err(|err|
+      format!("Unable to find the config directory\n\n{}", err)
+    ),
+    Ok
+  )
+}
+
+fn load_config(options: &Options) -> Result<Config, String> {
+  Config::load(&config_path(options)?).map_err(|err| format!("Unable to read config file\n\n{}", err))
+}
+
+// the configured ids, or the official extension's if none are configured
+fn configured_ids(config: &Config) -> (Vec<String>, Vec<String>) {
+  let extension_ids = if config.extension_ids.is_empty() { vec![extension::ID.to_string()] } else { config.extension_ids.clone() };
+  let addon_ids = if config.addon_ids.is_empty() { vec![extension::ADDON_ID.to_string()] } else { config.addon_ids.clone() };
+
+  (extension_ids, addon_ids)
+}
+
+fn merge(mut ids: Vec<String>, extra: &[String]) -> Vec<String> {
+  for id in extra {
+    if !ids.contains(id) {
+      ids.push(id.clone());
+    }
+  }
+
+  ids
+}
+
+// an empty list in the config means the official extension's id, so the last id can't be disallowed without allowing another first
+fn without(ids: Vec<String>, removed: &[String], kind: &str) -> Result<Vec<String>, String> {
+  let remaining = ids.iter().filter(|id| !removed.contains(id)).cloned().collect::<Vec<_>>();
+
+  if remaining.is_empty() && !removed.is_empty() {
+    return Err(format!("Unable to disallow {}, at least one {} ID has to stay allowed
+
+Allow another {} ID first", ids.join(", "), kind, kind));
+  }
+
+  Ok(remaining)
+}
+
+// every chrome extension id and firefox add-on id that should be allowed to talk to the host
+pub fn allowed_ids(options: &Options) -> Result<(Vec<String>, Vec<String>), String> {
+  let (extension_ids, addon_ids) = configured_ids(&load_config(options)?);
+
+  Ok((merge(extension_ids, &options.extension_ids), merge(addon_ids, &options.addon_ids)))
+}
+
+fn installed_browsers(scope: Scope) -> Result<Vec<Browser>, String> {
+  extension::is_installed(extension::NAME, scope).map(|statuses|
+    statuses.iter().filter(|(_, installed)| *installed).map(|(browser, _)| *browser).collect()
+  ).map_err(|err|
+    format!("Unable to check Archiver native extension installation status\n\n{}", err)
+  )
+}
+
+pub fn toggle() -> Result<String, String> {
+  // double-clicking a moved executable should fix its registration rather than uninstall it
+  let repaired = Browser::ALL.iter().cloned().map(|browser|
+    extension::repair(extension::NAME, browser, Scope::User).map(|repaired| (browser, repaired))
+  ).collect::<Result<Vec<_>, _>>().map_err(|err|
+    format!("Unable to check Archiver native extension installation status\n\n{}", err)
+  )?.into_iter().filter(|(_, repaired)| *repaired).map(|(browser, _)| browser).collect::<Vec<_>>();
+
+  if !repaired.is_empty() {
+    return Ok(format!("Archiver native extension moved, registration updated for {}", browser_list(&repaired)));
+  }
+
+  let installed = installed_browsers(Scope::User)?;
+
+  if !installed.is_empty() {
+    uninstall(&Options { browsers: installed, ..Default::default() })
+  }
+  else {
+    install(&Options::default())
+  }
+}
+
+pub fn install(options: &Options) -> Result<String, String> {
+  let scope = scope(options);
+  let browsers = if options.browsers.is_empty() { extension::detect() } else { options.browsers.clone() };
+  let (extension_ids, addon_ids) = allowed_ids(options)?;
+
+  extension::install(
+    extension::NAME,
+    extension::DESCRIPTION,
+    &extension_ids,
+    &addon_ids,
+    options.manifest_directory.as_deref(),
+    scope,
+    &browsers
+  ).map_or_else(
+    |err| Err(format!("Unable to install Archiver native extension\n\n{}", describe_error(err, scope))),
+    |_| Ok(format!("Archiver native extension installed{} for {}", installed_for(scope), browser_list(&browsers)))
+  )
+}
+
+pub fn uninstall(options: &Options) -> Result<String, String> {
+  let scope = scope(options);
+  let browsers = if options.browsers.is_empty() { installed_browsers(scope)? } else { options.browsers.clone() };
+
+  if browsers.is_empty() {
+    return Ok(format!("Archiver native extension is not installed{}", installed_for(scope)));
+  }
+
+  extension::uninstall(extension::NAME, scope, &browsers).map_or_else(
+    |err| Err(format!("Unable to uninstall Archiver native extension\n\n{}", describe_error(err, scope))),
+    |uninstalled| Ok(format!("Archiver native extension uninstalled{} from {}", installed_for(scope), browser_list(&uninstalled)))
+  )
+}
+
+// changes the allowed ids of an existing installation in place, and remembers the change in the config file
+pub fn update_allowed(options: &Options, allow: bool) -> Result<String, String> {
+  let scope = scope(options);
+
+  if options.extension_ids.is_empty() && options.addon_ids.is_empty() {
+    return Err("Expected at least one --extension-id or --addon-id".to_string());
+  }
+
+  let browsers = if options.browsers.is_empty() { Browser::ALL.to_vec() } else { options.browsers.clone() };
+
+  let path = config_path(options)?;
+  let mut config = load_config(options)?;
+  let (extension_ids, addon_ids) = configured_ids(&config);
+
+  if allow {
+    config.extension_ids = merge(extension_ids, &options.extension_ids);
+    config.addon_ids = merge(addon_ids, &options.addon_ids);
+  }
+  else {
+    config.extension_ids = without(extension_ids, &options.extension_ids, "extension")?;
+    config.addon_ids = without(addon_ids, &options.addon_ids, "add-on")?;
+  }
+
+  let updated = extension::update_allowed(extension::NAME, scope, &browsers, &options.extension_ids, &options.addon_ids, allow).map_err(|err|
+    format!("Unable to update Archiver native extension manifests\n\n{}", describe_error(err, scope))
+  )?;
+
+  config.save(&path).map_err(|err| format!("Unable to write config file\n\n{}", err))?;
+
+  if updated.is_empty() {
+    Ok(format!("Archiver native extension is not installed, saved allowed IDs to {}", path.display()))
+  }
+  else {
+    Ok(format!("Archiver native extension allowed IDs updated for {}", browser_list(&updated)))
+  }
+}
+
+// Ok(true) if the host is installed for any of the requested browsers and scopes
+pub fn status(options: &Options, out: &mut impl io::Write) -> Result<bool, String> {
+  let browsers = if options.browsers.is_empty() { Browser::ALL.to_vec() } else { options.browsers.clone() };
+  let scopes = scopes(options);
+  let mut any_installed = false;
+  let mut warnings = Vec::new();
+
+  let checking = |err| format!("Unable to check Archiver native extension installation status\n\n{}", err);
+
+  writeln!(out, "browser    user            system").map_err(|err| err.to_string())?;
+
+  for browser in browsers {
+    let mut columns = Vec::new();
+
+    for scope in Scope::ALL {
+      let installed = extension::is_installed_for(extension::NAME, browser, scope).map_err(checking)?;
+
+      any_installed |= installed && scopes.contains(&scope);
+      columns.push(if installed { "installed" } else { "not installed" });
+    }
+
+    writeln!(out, "{:<10} {:<15} {}", browser.id(), columns[0], columns[1]).map_err(|err| err.to_string())?;
+
+    if extension::conflicts(extension::NAME, browser).map_err(checking)? {
+      warnings.push(format!("warning: {} has different user and system-wide manifests, only the user one will be used", browser));
+    }
+  }
+
+  for warning in warnings {
+    writeln!(out, "{}", warning).map_err(|err| err.to_string())?;
+  }
+
+  Ok(any_installed)
+}
+
+// does the same rescan the host does before answering the extension, so a large archive can be indexed ahead of time
+// progress is written to stderr as it goes, on a single line that keeps being overwritten
+pub fn index(options: &Options) -> Result<String, String> {
+  let roots = load_config(options)?.settings.roots;
+
+  if roots.is_empty() {
+    return Err("No archive directory set, pick one on the extension's options page".to_string());
+  }
+
+  let mut indexed = Vec::new();
+
+  for root in roots {
+    let directory = Path::new(&root);
+
+    if !directory.is_dir() {
+      return Err(format!("{} doesn't exist", root));
+    }
+
+    let mut index = Index::load(directory);
+    let report = |progress: &Progress| eprint!("\r{:<79}", format!("{}: {}", root, progress));
+    let changes = index.scan().run(&report, &AtomicBool::new(false)).map_err(|err| format!("\nUnable to index {}\n\n{}", root, err))?;
+    index.apply(changes);
+
+    index.save().map_err(|err| format!("\nUnable to write the index of {}\n\n{}", root, err))?;
+    eprint!("\r{:<79}\r", "");
+
+    indexed.push(format!("{}: {} unique files in {} folders", root, index.names().len(), index.folders().len()));
+  }
+
+  Ok(indexed.join("\n"))
+}
@@ -0,0 +1,110 @@
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+pub const CONFIG_PATH: &str = "config.json";
+
+// settings shared by every copy of the host on this machine
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+  // chrome extension ids allowed to talk to the host, in addition to any given on the command line
+  pub extension_ids: Vec<String>,
+  // firefox add-on ids allowed to talk to the host, in addition to any given on the command line
+  pub addon_ids: Vec<String>,
+  // what the extension can see and change through GetConfig and SetConfig
+  #[serde(flatten)]
+  pub settings: Settings,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+  // folders that are searched for already archived files, new files are saved to the first one
+  pub roots: Vec<String>,
+  pub filename: FilenamePolicy,
+  pub hash: HashAlgorithm,
+  pub limits: Limits,
+  pub downloads: Downloads,
+}
+
+impl Settings {
+  // where new files are saved, if anywhere has been set up yet
+  pub fn root(&self) -> Option<&String> {
+    self.roots.first()
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all="lowercase")]
+pub enum FilenamePolicy {
+  // the name the file had when it was uploaded, if the page shows it
+  #[default]
+  Original,
+  // the name the server stores the file under
+  Server,
+}
+
+// the algorithm the hashes in Get and Set messages are in, boards only publish md5 so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all="lowercase")]
+pub enum HashAlgorithm {
+  #[default]
+  Md5,
+}
+
+impl HashAlgorithm {
+  pub const ALL: [HashAlgorithm; 1] = [HashAlgorithm::Md5];
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Limits {
+  // downloads larger than this many bytes are thrown away
+  pub max_file_size: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Downloads {
+  // seconds to wait for the server to accept a connection
+  pub connect_timeout: u64,
+  // seconds to wait for the server to send anything, before the attempt is given up on
+  pub read_timeout: u64,
+  // how many times a download is tried before giving up, when the server is busy or the connection drops
+  pub attempts: u32,
+  // milliseconds to wait before trying again, doubled after every attempt
+  pub backoff: u64,
+}
+
+impl Default for Downloads {
+  fn default() -> Downloads {
+    Downloads { connect_timeout: 10, read_timeout: 30, attempts: 4, backoff: 1000 }
+  }
+}
+
+impl Config {
+  // a missing config file is the same as an empty one
+  pub fn load(path: &Path) -> Result<Config, io::Error> {
+    match File::open(path) {
+      Ok(file) => serde_json::from_reader(BufReader::new(file)).map_err(|err|
+        io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err))
+      ),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+      Err(err) => Err(err)
+    }
+  }
+
+  pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+      std::fs::create_dir_all(parent)?;
+    }
+
+    serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+
+    Ok(())
+  }
+}
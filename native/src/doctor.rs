@@ -0,0 +1,236 @@
+use std::io;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use serde_json::json;
+use archive::framing;
+use crate::cli;
+use crate::cli::Options;
+use crate::extension;
+use crate::extension::Browser;
+use crate::extension::Scope;
+
+pub const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+  Ok,
+  Warning,
+  Failed,
+}
+
+struct Check {
+  status: Status,
+  description: String,
+}
+
+impl Check {
+  fn ok(description: impl Into<String>) -> Check {
+    Check { status: Status::Ok, description: description.into() }
+  }
+
+  fn warning(description: impl Into<String>) -> Check {
+    Check { status: Status::Warning, description: description.into() }
+  }
+
+  fn failed(description: impl Into<String>) -> Check {
+    Check { status: Status::Failed, description: description.into() }
+  }
+}
+
+// what fixes a broken registration, since doctor only reports problems and leaves them for the user to fix
+fn install_command(scope: Scope) -> &'static str {
+  match scope {
+    Scope::User => "archive install --user",
+    Scope::System => "archive install --system",
+  }
+}
+
+fn describe(value: Option<&str>) -> String {
+  value.map_or("missing".to_string(), |value| format!("\"{}\"", value))
+}
+
+// sends a single framed ping to the host at `exe_path`, launched the same way `browser` would launch it
+fn ping(exe_path: &Path, browser: Browser, manifest_path: &Path, extension_id: &str, addon_id: &str) -> Result<serde_json::Value, String> {
+  let mut command = Command::new(exe_path);
+
+  match browser {
+    Browser::Firefox => command.arg(manifest_path).arg(addon_id),
+    _ => command.arg(extension::origin(extension_id)),
+  };
+
+  let mut child = command.stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn().map_err(|err|
+    format!("unable to start host: {}", err)
+  )?;
+
+  let mut stdin = child.stdin.take().unwrap();
+  let mut stdout = child.stdout.take().unwrap();
+
+  let written = framing::write_frame(&mut stdin, &json!({ "Ping": null }), framing::MAX_INCOMING_SIZE);
+
+  // closing stdin lets the host exit once it has answered
+  drop(stdin);
+
+  let (sender, receiver) = mpsc::channel();
+
+  thread::spawn(move || {
+    sender.send(framing::read_frame(&mut stdout, framing::MAX_INCOMING_SIZE)).ok();
+  });
+
+  let response = receiver.recv_timeout(PING_TIMEOUT);
+
+  child.kill().ok();
+  child.wait().ok();
+
+  written.map_err(|err| format!("unable to send ping: {}", err))?;
+
+  match response {
+    Ok(Ok(response)) => Ok(response),
+    Ok(Err(err)) if err.is_recoverable() => Err(format!("host answered with an invalid message: {}", err)),
+    Ok(Err(err)) => Err(format!("host exited without answering: {}", err)),
+    Err(_) => Err(format!("host did not answer within {} seconds", PING_TIMEOUT.as_secs())),
+  }
+}
+
+// the checks for `browser` at `scope`, and whether anything is registered for it at all
+fn check_browser(browser: Browser, scope: Scope, extension_ids: &[String], addon_ids: &[String]) -> Result<(bool, Vec<Check>), io::Error> {
+  let mut checks = Vec::new();
+  let expected_path = extension::manifest_path(extension::NAME, browser, scope)?;
+
+  let (manifest_path, file) = match extension::registered_manifest(extension::NAME, browser, scope)? {
+    Some(registered) => registered,
+    None => {
+      checks.push(Check::failed(format!("no manifest registered (expected at {}), run `{}` to register it", expected_path.display(), install_command(scope))));
+      return Ok((false, checks));
+    }
+  };
+
+  checks.push(Check::ok(format!("manifest registered at {}", manifest_path.display())));
+
+  let manifest: serde_json::Value = match serde_json::from_reader(BufReader::new(file)) {
+    Ok(manifest) => manifest,
+    Err(err) => {
+      checks.push(Check::failed(format!("manifest is not valid JSON: {}", err)));
+      return Ok((true, checks));
+    }
+  };
+
+  checks.push(Check::ok("manifest is valid JSON"));
+
+  match manifest.get("name").and_then(|name| name.as_str()) {
+    Some(extension::NAME) => checks.push(Check::ok(format!("name is {}", extension::NAME))),
+    name => checks.push(Check::failed(format!("name is {}, expected {}", describe(name), extension::NAME))),
+  }
+
+  match manifest.get("type").and_then(|host_type| host_type.as_str()) {
+    Some("stdio") => checks.push(Check::ok("type is stdio")),
+    host_type => checks.push(Check::failed(format!("type is {}, expected stdio", describe(host_type)))),
+  }
+
+  let (allowed_key, expected) = extension::allowed(browser, extension_ids, addon_ids);
+
+  for allowed in expected {
+    let allows = manifest.get(allowed_key).and_then(|values| values.as_array()).is_some_and(|values|
+      values.iter().any(|value| value.as_str() == Some(&allowed))
+    );
+
+    if allows {
+      checks.push(Check::ok(format!("{} contains {}", allowed_key, allowed)));
+    }
+    else {
+      checks.push(Check::failed(format!("{} does not contain {}", allowed_key, allowed)));
+    }
+  }
+
+  let exe_path = match manifest.get("path").and_then(|path| path.as_str()).map(Path::new) {
+    Some(exe_path) if exe_path.is_file() => {
+      checks.push(Check::ok(format!("path {} exists", exe_path.display())));
+      exe_path
+    },
+    Some(exe_path) => {
+      checks.push(Check::failed(format!("path {} does not exist, run `{}` from where the executable is now to point it there", exe_path.display(), install_command(scope))));
+      return Ok((true, checks));
+    },
+    None => {
+      checks.push(Check::failed("path is missing"));
+      return Ok((true, checks));
+    }
+  };
+
+  // compared here rather than with extension::is_installed_for, which repairs what it finds
+  let current_exe = std::env::current_exe()?;
+
+  if exe_path != current_exe {
+    checks.push(Check::warning(format!("path points to a different executable than {}", current_exe.display())));
+  }
+
+  match ping(exe_path, browser, &manifest_path, &extension_ids[0], &addon_ids[0]) {
+    Ok(response) if response.get("type").and_then(|response_type| response_type.as_str()) == Some("pong") => {
+      checks.push(Check::ok("host answered ping"));
+    },
+    Ok(response) => {
+      checks.push(Check::warning(format!("host answered ping with {}, it may be outdated", response)));
+    },
+    Err(err) => {
+      checks.push(Check::failed(err));
+    }
+  }
+
+  if scope == Scope::User && extension::conflicts(extension::NAME, browser)? {
+    checks.push(Check::warning("a different system-wide manifest is also registered and will be ignored"));
+  }
+
+  Ok((true, checks))
+}
+
+// Ok(true) if the host is correctly installed for at least one browser and broken for none of the browsers it's registered with
+pub fn run(options: &Options, out: &mut impl Write) -> Result<bool, String> {
+  let browsers = if options.browsers.is_empty() { Browser::ALL.to_vec() } else { options.browsers.clone() };
+  let (extension_ids, addon_ids) = cli::allowed_ids(options)?;
+  let mut healthy = 0;
+  let mut broken = 0;
+
+  for (browser, scope) in browsers.iter().flat_map(|browser| cli::scopes(options).into_iter().map(move |scope| (*browser, scope))) {
+    let (registered, checks) = check_browser(browser, scope, &extension_ids, &addon_ids).map_err(|err|
+      format!("Unable to check {}: {}", browser, err)
+    )?;
+
+    // missing registrations are only worth reporting if they were asked about, or if it's the user's own browser
+    let requested = !options.browsers.is_empty() && options.scope.is_some();
+
+    if !registered && !requested && (scope == Scope::System || (options.browsers.is_empty() && !extension::detect().contains(&browser))) {
+      continue;
+    }
+
+    match scope {
+      Scope::User => writeln!(out, "{}", browser),
+      Scope::System => writeln!(out, "{} (system-wide)", browser),
+    }.map_err(|err| err.to_string())?;
+
+    for check in &checks {
+      let status = match check.status {
+        Status::Ok => "ok",
+        Status::Warning => "warn",
+        Status::Failed => "FAIL",
+      };
+
+      writeln!(out, "  [{:<4}] {}", status, check.description).map_err(|err| err.to_string())?;
+    }
+
+    if checks.iter().any(|check| check.status == Status::Failed) {
+      if registered || requested {
+        broken += 1;
+      }
+    }
+    else {
+      healthy += 1;
+    }
+  }
+
+  Ok(healthy > 0 && broken == 0)
+}
@@ -0,0 +1,258 @@
+use serde::Serialize;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::config::Downloads;
+use crate::error::Error;
+
+// downloads are written to a hidden file next to where they'll end up, and only renamed into place once they're complete
+// so a host that's killed partway through leaves nothing behind that looks like an archived file
+
+const TEMPORARY_SUFFIX: &str = ".part";
+
+// so temporary files from different threads in the same host never collide, the process id takes care of other hosts
+static NEXT_TEMPORARY: AtomicU64 = AtomicU64::new(0);
+
+// whether a file in the archive is a download that hasn't finished, which shouldn't be indexed
+pub fn is_temporary(filename: &str) -> bool {
+  filename.starts_with('.') && filename.ends_with(TEMPORARY_SUFFIX)
+}
+
+// creates an empty hidden file in the folder to download into
+pub fn temporary_file(folder: &Path, filename: &str) -> Result<PathBuf, io::Error> {
+  loop {
+    let path = folder.join(format!(".{}.{}-{}{}", filename, process::id(), NEXT_TEMPORARY.fetch_add(1, Ordering::Relaxed), TEMPORARY_SUFFIX));
+
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+      Ok(_) => return Ok(path),
+      Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+      Err(err) => return Err(err)
+    }
+  }
+}
+
+// moves a finished download to the requested name, or the first of "name (1).ext", "name (2).ext" and so on that's free
+// linking only succeeds if nothing has the name yet, so two downloads can't pick the same one, and the name never refers to a partial file
+pub fn persist(temporary: &Path, requested: &Path) -> Result<PathBuf, io::Error> {
+  let stem = requested.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+  let extension = requested.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+
+  let mut path = requested.to_path_buf();
+  let mut number: u32 = 0;
+
+  loop {
+    match claim(temporary, &path) {
+      Ok(()) => return Ok(path),
+      Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+        number += 1;
+        path.set_file_name(format!("{} ({}){}", stem, number, extension));
+      },
+      Err(err) => return Err(err)
+    }
+  }
+}
+
+fn claim(temporary: &Path, path: &Path) -> Result<(), io::Error> {
+  match fs::hard_link(temporary, path) {
+    Ok(()) => fs::remove_file(temporary),
+    Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Err(err),
+    // filesystems without hard links, like FAT, get an empty file created in its place and then replaced
+    Err(_) => {
+      OpenOptions::new().write(true).create_new(true).open(path)?;
+
+      fs::rename(temporary, path).inspect_err(|_| {
+        fs::remove_file(path).ok();
+      })
+    }
+  }
+}
+
+// waits between attempts never go over this, even when the server asks for longer
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// how often a wait between attempts checks whether it's been cancelled
+const CANCEL_INTERVAL: Duration = Duration::from_millis(100);
+
+// what happened on one try at downloading a file, sent back with the error when every try fails
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Attempt {
+  // what the server answered with, if it got that far
+  pub status: Option<u16>,
+  // how much of the file was already there, when resuming
+  pub offset: u64,
+  pub received: u64,
+  pub elapsed_ms: u64,
+  // why the attempt failed, if it did
+  pub error: Option<String>,
+}
+
+// why an attempt failed, and whether it's worth trying again
+enum Failure {
+  Status(u16, Option<Duration>),
+  // what was already downloaded couldn't be resumed and has been thrown away, so it's worth trying again from the start straight away
+  Restart(String),
+  Network(String),
+  Fatal(Error),
+}
+
+// downloads files over HTTP, trying again when the server is busy or the connection drops
+// one client is shared by every download in a message, so they can reuse connections
+pub struct Client {
+  agent: ureq::Agent,
+  settings: Downloads,
+}
+
+impl Client {
+  pub fn new(settings: &Downloads) -> Client {
+    let agent = ureq::AgentBuilder::new()
+      .timeout_connect(Duration::from_secs(settings.connect_timeout))
+      .timeout_read(Duration::from_secs(settings.read_timeout))
+      .user_agent(concat!("archive/", env!("CARGO_PKG_VERSION")))
+      .build();
+
+    Client { agent, settings: settings.clone() }
+  }
+
+  // downloads url into path, picking up where the last attempt left off when the server supports ranges
+  // cancelled is checked as the file arrives and while waiting to try again
+  pub fn download(&self, url: &str, path: &Path, cancelled: &dyn Fn() -> bool) -> Result<Vec<Attempt>, Error> {
+    let mut attempts = Vec::new();
+    let mut backoff = Duration::from_millis(self.settings.backoff);
+    let mut restarts = 0;
+
+    loop {
+      if cancelled() {
+        return Err(Error::Cancelled)
+      }
+
+      let started = Instant::now();
+      let offset = fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+      let mut received = 0;
+
+      let failure = match self.attempt(url, path, offset, &mut received, cancelled) {
+        Ok(status) => {
+          attempts.push(Attempt { status: Some(status), offset, received, elapsed_ms: started.elapsed().as_millis() as u64, error: None });
+
+          return Ok(attempts)
+        },
+        Err(failure) => failure
+      };
+
+      let (status, reason, wait) = match failure {
+        Failure::Fatal(error) => return Err(error),
+        // doesn't count as one of the attempts, and the next one has nothing to resume from so it can't end up here again
+        Failure::Restart(reason) if offset > 0 => {
+          attempts.push(Attempt { status: None, offset, received, elapsed_ms: started.elapsed().as_millis() as u64, error: Some(reason) });
+          restarts += 1;
+          continue
+        },
+        Failure::Status(status, retry_after) => (Some(status), format!("HTTP status {}", status), retry_after),
+        Failure::Network(reason) | Failure::Restart(reason) => (None, reason, None),
+      };
+
+      attempts.push(Attempt { status, offset, received, elapsed_ms: started.elapsed().as_millis() as u64, error: Some(reason.clone()) });
+
+      // anything else from the server, like a 404, isn't going to change by asking again
+      let retry = status.is_none_or(|status| status == 429 || status >= 500);
+
+      if !retry || attempts.len() - restarts >= self.settings.attempts as usize {
+        return Err(match status {
+          Some(status) => Error::Http { url: url.to_string(), status, attempts },
+          None => Error::Network { url: url.to_string(), reason, attempts },
+        })
+      }
+
+      wait_for(wait.unwrap_or(backoff).max(backoff).min(MAX_BACKOFF), cancelled);
+      backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+    }
+  }
+
+  // returns the status the file was downloaded with, adding to what's already in path if the server sends the rest
+  fn attempt(&self, url: &str, path: &Path, offset: u64, received: &mut u64, cancelled: &dyn Fn() -> bool) -> Result<u16, Failure> {
+    let mut request = self.agent.get(url);
+
+    if offset > 0 {
+      request = request.set("Range", &format!("bytes={}-", offset));
+    }
+
+    let response = match request.call() {
+      Ok(response) => response,
+      // asked for a range starting at the end of the file, so it was already complete
+      Err(ureq::Error::Status(416, response)) if response.header("Content-Range").is_some_and(|range| range == format!("bytes */{}", offset)) => {
+        return Ok(416)
+      },
+      Err(ureq::Error::Status(416, response)) => {
+        truncate(path)?;
+        return Err(Failure::Restart(format!("Asked for bytes {}- and got {}", offset, response.header("Content-Range").unwrap_or("HTTP status 416"))))
+      },
+      Err(ureq::Error::Status(status, response)) => {
+        let retry_after = response.header("Retry-After").and_then(|seconds| seconds.trim().parse().ok()).map(Duration::from_secs);
+        return Err(Failure::Status(status, retry_after))
+      },
+      Err(ureq::Error::Transport(transport)) => return Err(Failure::Network(transport.to_string())),
+    };
+
+    let status = response.status();
+
+    // servers that ignore the range send the whole file again
+    let resumed = status == 206 && response.header("Content-Range").is_some_and(|range| range.starts_with(&format!("bytes {}-", offset)));
+
+    if status == 206 && !resumed {
+      truncate(path)?;
+      return Err(Failure::Restart(format!("Asked for bytes {}- and got {}", offset, response.header("Content-Range").unwrap_or("something else"))))
+    }
+
+    let mut file = OpenOptions::new().write(true).append(resumed).truncate(!resumed).open(path).map_err(|err|
+      Failure::Fatal(Error::io(path, err))
+    )?;
+
+    let mut reader = response.into_reader();
+    let mut buffer = [0; 64 * 1024];
+
+    loop {
+      if cancelled() {
+        return Err(Failure::Fatal(Error::Cancelled))
+      }
+
+      let read = match reader.read(&mut buffer) {
+        Ok(0) => break,
+        Ok(read) => read,
+        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+        // whatever arrived is kept, so the next attempt can resume from it
+        Err(err) => return Err(Failure::Network(err.to_string())),
+      };
+
+      file.write_all(&buffer[..read]).map_err(|err| Failure::Fatal(Error::io(path, err)))?;
+      *received += read as u64;
+    }
+
+    file.flush().map_err(|err| Failure::Fatal(Error::io(path, err)))?;
+
+    Ok(status)
+  }
+}
+
+fn truncate(path: &Path) -> Result<(), Failure> {
+  OpenOptions::new().write(true).truncate(true).open(path).map(|_| ()).map_err(|err| Failure::Fatal(Error::io(path, err)))
+}
+
+// sleeps for duration, or until cancelled
+fn wait_for(duration: Duration, cancelled: &dyn Fn() -> bool) {
+  let until = Instant::now() + duration;
+
+  while !cancelled() {
+    let now = Instant::now();
+
+    if now >= until {
+      return
+    }
+
+    thread::sleep((until - now).min(CANCEL_INTERVAL));
+  }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::{json, Value};
+use crate::download::Attempt;
+
+// everything that can go wrong while handling a message, sent to the extension as
+// {"code": ..., "error": ..., "details": ...} where code never changes between versions
+#[derive(Debug, PartialEq)]
+pub enum Error {
+  InvalidMessage {
+    reason: String,
+  },
+  // no archive directory has been set, or it doesn't exist
+  DirectoryMissing {
+    path: Option<String>,
+  },
+  PermissionDenied {
+    path: String,
+    reason: String,
+  },
+  // the server's answer to the last attempt, after retrying if it was worth it
+  Http {
+    url: String,
+    status: u16,
+    attempts: Vec<Attempt>,
+  },
+  // the server couldn't be reached or stopped answering, on every attempt
+  Network {
+    url: String,
+    reason: String,
+    attempts: Vec<Attempt>,
+  },
+  // more than limits.max_file_size in the config
+  TooLarge {
+    path: String,
+    size: u64,
+    limit: u64,
+  },
+  DiskFull {
+    path: String,
+  },
+  HashMismatch {
+    path: String,
+    expected: String,
+    actual: String,
+  },
+  // a name from the extension that would end up outside the archive directory
+  PathRejected {
+    path: String,
+    reason: String,
+  },
+  // something this machine can't do, like showing a folder picker without a display
+  Unavailable {
+    reason: String,
+  },
+  Io {
+    path: String,
+    reason: String,
+  },
+  Panic {
+    reason: String,
+    file: Option<String>,
+    line: Option<u32>,
+  },
+  // stopped by a Cancel message before it finished
+  Cancelled,
+}
+
+impl Error {
+  // sorts an io error into the kinds the extension can do something about
+  pub fn io(path: &Path, err: io::Error) -> Error {
+    let path = path.display().to_string();
+
+    match err.kind() {
+      io::ErrorKind::PermissionDenied => Error::PermissionDenied { path, reason: err.to_string() },
+      io::ErrorKind::StorageFull => Error::DiskFull { path },
+      _ => Error::Io { path, reason: err.to_string() }
+    }
+  }
+
+  pub fn code(&self) -> &'static str {
+    match self {
+      Error::InvalidMessage { .. } => "invalid_message",
+      Error::DirectoryMissing { .. } => "directory_missing",
+      Error::PermissionDenied { .. } => "permission_denied",
+      Error::Http { .. } => "http",
+      Error::Network { .. } => "network",
+      Error::TooLarge { .. } => "too_large",
+      Error::DiskFull { .. } => "disk_full",
+      Error::HashMismatch { .. } => "hash_mismatch",
+      Error::PathRejected { .. } => "path_rejected",
+      Error::Unavailable { .. } => "unavailable",
+      Error::Io { .. } => "io",
+      Error::Panic { .. } => "panic",
+      Error::Cancelled => "cancelled",
+    }
+  }
+
+  pub fn details(&self) -> Option<Value> {
+    match self {
+      Error::InvalidMessage { .. } | Error::Unavailable { .. } | Error::Cancelled => None,
+      Error::DirectoryMissing { path } => path.as_ref().map(|path| json!({ "path": path })),
+      Error::PermissionDenied { path, .. } | Error::DiskFull { path } | Error::PathRejected { path, .. } | Error::Io { path, .. } => Some(json!({ "path": path })),
+      Error::Http { url, status, attempts } => Some(json!({ "url": url, "status": status, "attempts": attempts })),
+      Error::Network { url, attempts, .. } => Some(json!({ "url": url, "attempts": attempts })),
+      Error::TooLarge { path, size, limit } => Some(json!({ "path": path, "size": size, "limit": limit })),
+      Error::HashMismatch { path, expected, actual } => Some(json!({ "path": path, "expected": expected, "actual": actual })),
+      Error::Panic { file, line, .. } => Some(json!({ "file": file, "line": line })),
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Error::InvalidMessage { reason } => write!(f, "Invalid message: {}", reason),
+      Error::DirectoryMissing { path: None } => write!(f, "No archive directory set"),
+      Error::DirectoryMissing { path: Some(path) } => write!(f, "{} doesn't exist", path),
+      Error::PermissionDenied { path, reason } => write!(f, "Not allowed to access {}: {}", path, reason),
+      Error::Http { url, status, .. } => write!(f, "Downloading {} failed with HTTP status {}", url, status),
+      Error::Network { url, reason, .. } => write!(f, "Downloading {} failed: {}", url, reason),
+      Error::TooLarge { path, size, limit } => write!(f, "{} is {} bytes, more than the {} byte limit", path, size, limit),
+      Error::DiskFull { path } => write!(f, "Not enough disk space to write {}", path),
+      Error::HashMismatch { path, expected, actual } => write!(f, "{} has hash {} instead of {}", path, actual, expected),
+      Error::PathRejected { path, reason } => write!(f, "Can't save to {}: {}", path, reason),
+      Error::Unavailable { reason } => write!(f, "{}", reason),
+      Error::Io { path, reason } => write!(f, "{}: {}", path, reason),
+      Error::Panic { reason, .. } => write!(f, "Panic: {}", reason),
+      Error::Cancelled => write!(f, "Cancelled"),
+    }
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl Serialize for Error {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let details = self.details();
+    let mut map = serializer.serialize_map(None)?;
+
+    map.serialize_entry("code", self.code())?;
+    map.serialize_entry("error", &self.to_string())?;
+
+    if let Some(details) = details {
+      map.serialize_entry("details", &details)?;
+    }
+
+    map.end()
+  }
+}
@@ -1,81 +1,333 @@
 use std::fs::File;
-use std::path::Path;
 use std::io::BufReader;
 use std::io;
 use std::io::BufWriter;
+use std::fmt;
+use std::str::FromStr;
+use std::path::Path;
+use std::path::PathBuf;
 use std::collections::HashMap;
 use serde_json::json;
-use winreg;
+use crate::platform::Native;
+use crate::platform::Platform;
 
 pub const NAME: &str = "com.dagwaging.archive";
 pub const DESCRIPTION: &str = "Simple archiver extension for drawthreads";
 pub const ID: &str = "fdnmnpnjacfjphfmhlfgjpmkimbekmnd";
-pub const NATIVE_MESSAGING_REGISTRY_KEY: &str = "Software\\Google\\Chrome\\NativeMessagingHosts\\";
-pub const HOST_PATH: &str = "host.json";
-
-pub fn is_installed(name: &str) -> Result<bool, io::Error> {
-  let file = winreg::RegKey::predef(winreg::enums::HKEY_CURRENT_USER).open_subkey(
-    format!("{}{}", NATIVE_MESSAGING_REGISTRY_KEY, name)
-  ).and_then(|key|
-    key.get_value("")
-  ).and_then(|value: String|
-    File::open(value)
-  ).map_or_else(|err|
+pub const ADDON_ID: &str = "archive@dagwaging.com";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Browser {
+  Chrome,
+  Chromium,
+  Brave,
+  Vivaldi,
+  Firefox,
+}
+
+impl Browser {
+  pub const ALL: [Browser; 5] = [Browser::Chrome, Browser::Chromium, Browser::Brave, Browser::Vivaldi, Browser::Firefox];
+}
+
+impl FromStr for Browser {
+  type Err = String;
+
+  fn from_str(string: &str) -> Result<Self, Self::Err> {
+    Browser::ALL.iter().cloned().find(|browser|
+      browser.id() == string.to_lowercase()
+    ).ok_or_else(||
+      format!("Unknown browser '{}', expected one of {}", string, Browser::ALL.iter().map(|browser| browser.id()).collect::<Vec<_>>().join(", "))
+    )
+  }
+}
+
+impl Browser {
+  // short name used on the command line
+  pub fn id(&self) -> &'static str {
+    match self {
+      Browser::Chrome => "chrome",
+      Browser::Chromium => "chromium",
+      Browser::Brave => "brave",
+      Browser::Vivaldi => "vivaldi",
+      Browser::Firefox => "firefox",
+    }
+  }
+}
+
+impl fmt::Display for Browser {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Browser::Chrome => "Google Chrome",
+      Browser::Chromium => "Chromium",
+      Browser::Brave => "Brave",
+      Browser::Vivaldi => "Vivaldi",
+      Browser::Firefox => "Firefox",
+    })
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+  User,
+  System,
+}
+
+impl Scope {
+  pub const ALL: [Scope; 2] = [Scope::User, Scope::System];
+}
+
+impl fmt::Display for Scope {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Scope::User => "user",
+      Scope::System => "system",
+    })
+  }
+}
+
+// browsers that appear to be installed for this user, falling back to chrome so there's always something to install into
+pub fn detect() -> Vec<Browser> {
+  let browsers: Vec<Browser> = Browser::ALL.iter().cloned().filter(|browser|
+    Native::detect(*browser)
+  ).collect();
+
+  if browsers.is_empty() {
+    vec![Browser::Chrome]
+  }
+  else {
+    browsers
+  }
+}
+
+// installation status at `scope` of every browser we know about, in the order of Browser::ALL
+pub fn is_installed(name: &str, scope: Scope) -> Result<Vec<(Browser, bool)>, io::Error> {
+  Browser::ALL.iter().map(|browser|
+    is_installed_for(name, *browser, scope).map(|installed| (*browser, installed))
+  ).collect()
+}
+
+// where install puts the manifest for `name` by default, whether or not it's there
+pub fn manifest_path(name: &str, browser: Browser, scope: Scope) -> Result<PathBuf, io::Error> {
+  Native::manifest_path(browser, scope, name, None)
+}
+
+// the manifest `browser` will load for `name` at `scope`, if one is registered and exists
+pub fn registered_manifest(name: &str, browser: Browser, scope: Scope) -> Result<Option<(PathBuf, File)>, io::Error> {
+  Native::registered_manifest(browser, scope, name)?.map(|manifest_path|
+    File::open(&manifest_path).map(|file| (manifest_path, file))
+  ).transpose().map_or_else(|err|
     match err.kind() {
       io::ErrorKind::NotFound => Ok(None),
       _ => Err(err)
     },
-    |file| Ok(Some(file))
-  )?;
+    Ok
+  )
+}
+
+fn write_manifest(manifest_path: &Path, manifest: &serde_json::Value) -> Result<(), io::Error> {
+  if let Some(parent) = manifest_path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+    std::fs::create_dir_all(parent)?;
+  }
+
+  let file = File::create(manifest_path)?;
+
+  serde_json::to_writer(BufWriter::new(file), manifest)?;
+
+  Ok(())
+}
+
+// points a registration left behind by a moved executable at this one, Ok(true) if anything had to change
+// a registration whose executable still exists belongs to some other copy of the host and is left alone
+pub fn repair(name: &str, browser: Browser, scope: Scope) -> Result<bool, io::Error> {
+  let registered_path = match Native::registered_manifest(browser, scope, name)? {
+    Some(registered_path) => registered_path,
+    None => return Ok(false)
+  };
+
+  // a manifest living next to the executable moves along with it
+  let manifest_path = if registered_path.exists() { registered_path.clone() } else { Native::manifest_path(browser, scope, name, None)? };
+
+  let mut manifest: serde_json::Value = match File::open(&manifest_path) {
+    Ok(file) => match serde_json::from_reader(BufReader::new(file)) {
+      Ok(manifest) => manifest,
+      Err(_) => return Ok(false)
+    },
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+    Err(err) => return Err(err)
+  };
+
+  if manifest.get("name").and_then(|manifest_name| manifest_name.as_str()) != Some(name) {
+    return Ok(false);
+  }
+
+  let exe_path = std::env::current_exe()?;
+  let host_path = manifest.get("path").and_then(|path| path.as_str()).map(PathBuf::from);
+  let moved = host_path.as_ref().is_none_or(|host_path| !host_path.exists());
+
+  if !moved && host_path.as_ref() != Some(&exe_path) {
+    return Ok(false);
+  }
+
+  if moved {
+    manifest["path"] = json!(exe_path);
+    write_manifest(&manifest_path, &manifest)?;
+  }
+
+  if manifest_path != registered_path {
+    Native::register(browser, scope, name, &manifest_path)?;
+  }
+
+  Ok(moved || manifest_path != registered_path)
+}
+
+pub fn is_installed_for(name: &str, browser: Browser, scope: Scope) -> Result<bool, io::Error> {
+  // a system-wide registration can only be repaired by an administrator, but its status can still be read
+  match repair(name, browser, scope) {
+    Err(err) if err.kind() != io::ErrorKind::PermissionDenied => return Err(err),
+    _ => {}
+  }
+
+  let file = registered_manifest(name, browser, scope)?.map(|(_, file)| file);
 
   let exe_path = std::env::current_exe()?;
 
   Ok(
     file.and_then(|file|
       serde_json::from_reader::<_, HashMap<String, serde_json::Value>>(BufReader::new(file)).ok()
-    ).map(|host_manifest|
+    ).and_then(|host_manifest|
       host_manifest.get("path").and_then(|path|
         path.as_str()
       ).zip(exe_path.to_str()).map(|(string, exe_path)|
-        string.to_string() == exe_path
+        string == exe_path
       )
-    ).flatten().unwrap_or(false)
+    ).unwrap_or(false)
   )
 }
 
-pub fn install(name: &str, description: &str, extension_id: &str) -> Result<(), io::Error> {
-  let exe_path = &std::env::current_exe()?;
+pub fn origin(extension_id: &str) -> String {
+  format!("chrome-extension://{}/", extension_id)
+}
 
-  let file = File::create(HOST_PATH)?;
+// firefox identifies extensions by add-on id rather than by origin
+pub fn allowed(browser: Browser, extension_ids: &[String], addon_ids: &[String]) -> (&'static str, Vec<String>) {
+  match browser {
+    Browser::Firefox => ("allowed_extensions", addon_ids.to_vec()),
+    _ => ("allowed_origins", extension_ids.iter().map(|extension_id| origin(extension_id)).collect())
+  }
+}
+
+pub fn manifest(browser: Browser, name: &str, description: &str, exe_path: &Path, extension_ids: &[String], addon_ids: &[String]) -> serde_json::Value {
+  let (allowed_key, allowed) = allowed(browser, extension_ids, addon_ids);
 
-  serde_json::to_writer(BufWriter::new(file), &json!({
+  let mut manifest = json!({
     "name": name,
     "description": description,
     "path": exe_path,
-    "type": "stdio",
-    "allowed_origins": [format!("chrome-extension://{}/", extension_id)]
-  }))?;
+    "type": "stdio"
+  });
+
+  manifest[allowed_key] = json!(allowed);
+
+  manifest
+}
 
-  let (key, _) = winreg::RegKey::predef(winreg::enums::HKEY_CURRENT_USER).create_subkey(
-    &Path::new(&format!("{}{}", NATIVE_MESSAGING_REGISTRY_KEY, name))
-  )?;
+// writes the manifest into `manifest_directory` if given, or wherever the platform expects it otherwise
+pub fn install(name: &str, description: &str, extension_ids: &[String], addon_ids: &[String], manifest_directory: Option<&Path>, scope: Scope, browsers: &[Browser]) -> Result<(), io::Error> {
+  let exe_path = &std::env::current_exe()?;
+  let manifest_directory = manifest_directory.map(std::path::absolute).transpose()?;
+
+  for browser in browsers {
+    let manifest_path = Native::manifest_path(*browser, scope, name, manifest_directory.as_deref())?;
 
-  // not really anything reasonable we can do if the path isn't valid UTF-8 since it has to go into json...
-  let host_filename = &exe_path.parent().unwrap().join(HOST_PATH).to_str().unwrap().to_string();
+    write_manifest(&manifest_path, &manifest(*browser, name, description, exe_path, extension_ids, addon_ids))?;
 
-  key.set_value("".to_string(), host_filename)?;
+    Native::register(*browser, scope, name, &manifest_path)?;
+  }
 
   Ok(())
 }
 
-pub fn uninstall(name: &str) -> Result<(), io::Error> {
-  if Path::new(HOST_PATH).exists() {
-    std::fs::remove_file(HOST_PATH)?;
+// adds (or removes, if `allow` is false) extensions in the manifests already registered for `browsers`
+// returns the browsers that had a manifest to change
+pub fn update_allowed(name: &str, scope: Scope, browsers: &[Browser], extension_ids: &[String], addon_ids: &[String], allow: bool) -> Result<Vec<Browser>, io::Error> {
+  let mut updated = Vec::new();
+
+  for browser in browsers {
+    let (manifest_path, file) = match registered_manifest(name, *browser, scope)? {
+      Some(registered) => registered,
+      None => continue
+    };
+
+    let mut manifest: serde_json::Value = serde_json::from_reader(BufReader::new(file)).map_err(|err|
+      io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", manifest_path.display(), err))
+    )?;
+
+    let (allowed_key, changes) = allowed(*browser, extension_ids, addon_ids);
+
+    let mut values: Vec<String> = manifest.get(allowed_key).and_then(|values| values.as_array()).map(|values|
+      values.iter().filter_map(|value| value.as_str().map(|value| value.to_string())).collect()
+    ).unwrap_or_default();
+
+    if allow {
+      values.extend(changes.into_iter().filter(|change| !values.contains(change)).collect::<Vec<_>>());
+    }
+    else {
+      values.retain(|value| !changes.contains(value));
+    }
+
+    manifest[allowed_key] = json!(values);
+
+    write_manifest(&manifest_path, &manifest)?;
+
+    updated.push(*browser);
   }
 
-  winreg::RegKey::predef(winreg::enums::HKEY_CURRENT_USER).delete_subkey(
-    format!("{}{}", NATIVE_MESSAGING_REGISTRY_KEY, name)
-  )?;
+  Ok(updated)
+}
 
-  Ok(())
+// returns every browser it was uninstalled from, which includes browsers that share a registration with the ones asked for
+pub fn uninstall(name: &str, scope: Scope, browsers: &[Browser]) -> Result<Vec<Browser>, io::Error> {
+  let mut uninstalled = browsers.to_vec();
+
+  for browser in browsers {
+    let manifest_path = Native::registered_manifest(*browser, scope, name)?;
+
+    Native::unregister(*browser, scope, name)?;
+
+    if manifest_path.is_some() {
+      for shared in Native::shares_registration(*browser) {
+        if !uninstalled.contains(&shared) {
+          uninstalled.push(shared);
+        }
+      }
+    }
+
+    // browsers can share a manifest, so only remove it once nothing refers to it anymore
+    if let Some(manifest_path) = manifest_path {
+      let still_registered = Browser::ALL.iter().flat_map(|other|
+        Scope::ALL.iter().map(|other_scope| Native::registered_manifest(*other, *other_scope, name))
+      ).collect::<Result<Vec<_>, _>>()?.contains(&Some(manifest_path.clone()));
+
+      if !still_registered && manifest_path.exists() {
+        std::fs::remove_file(manifest_path)?;
+      }
+    }
+  }
+
+  Ok(uninstalled)
+}
+
+// whether `browser` has both a user and a system-wide registration for `name` that disagree with each other
+// the user registration wins, so the system-wide one is silently ignored
+pub fn conflicts(name: &str, browser: Browser) -> Result<bool, io::Error> {
+  let manifests = Scope::ALL.iter().map(|scope|
+    registered_manifest(name, browser, *scope).map(|registered|
+      registered.map(|(_, file)| serde_json::from_reader::<_, serde_json::Value>(BufReader::new(file)).ok())
+    )
+  ).collect::<Result<Vec<_>, _>>()?;
+
+  Ok(match manifests.as_slice() {
+    [Some(user), Some(system)] => user != system,
+    _ => false
+  })
 }
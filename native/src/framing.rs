@@ -0,0 +1,119 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use serde::Serialize;
+use serde_json::Value;
+
+// browsers send messages as a native-endian u32 length followed by that many bytes of UTF-8 JSON, and expect the same back
+
+// the largest message accepted from the browser, chrome allows up to 4 GB but nothing we expect comes close
+pub const MAX_INCOMING_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum FrameError {
+  // the other side closed the stream between messages, which is how browsers tell the host to exit
+  Closed,
+  // the stream ended partway through a message
+  Truncated,
+  // when reading, the message has been skipped so the next one can still be read
+  TooLarge {
+    size: usize,
+    limit: usize,
+  },
+  InvalidUtf8(std::str::Utf8Error),
+  InvalidJson(serde_json::Error),
+  Io(io::Error),
+}
+
+impl FrameError {
+  // whether the stream is still usable after this error
+  pub fn is_recoverable(&self) -> bool {
+    matches!(self, FrameError::TooLarge { .. } | FrameError::InvalidUtf8(_) | FrameError::InvalidJson(_))
+  }
+}
+
+impl fmt::Display for FrameError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      FrameError::Closed => write!(f, "The stream was closed"),
+      FrameError::Truncated => write!(f, "The stream ended in the middle of a message"),
+      FrameError::TooLarge { size, limit } => write!(f, "The message is {} bytes, more than the {} byte limit", size, limit),
+      FrameError::InvalidUtf8(err) => write!(f, "The message isn't valid UTF-8: {}", err),
+      FrameError::InvalidJson(err) => write!(f, "The message isn't valid JSON: {}", err),
+      FrameError::Io(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+  fn from(err: io::Error) -> Self {
+    match err.kind() {
+      io::ErrorKind::BrokenPipe => FrameError::Closed,
+      io::ErrorKind::UnexpectedEof => FrameError::Truncated,
+      _ => FrameError::Io(err)
+    }
+  }
+}
+
+// like read_exact, but tells a stream that ended before the first byte apart from one that ended partway through
+fn fill(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize, io::Error> {
+  let mut filled = 0;
+
+  while filled < buffer.len() {
+    match reader.read(&mut buffer[filled..]) {
+      Ok(0) => break,
+      Ok(read) => filled += read,
+      Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+      Err(err) => return Err(err)
+    }
+  }
+
+  Ok(filled)
+}
+
+pub fn read_frame(reader: &mut impl Read, limit: usize) -> Result<Value, FrameError> {
+  let mut length = [0; 4];
+
+  match fill(reader, &mut length)? {
+    0 => return Err(FrameError::Closed),
+    4 => {},
+    _ => return Err(FrameError::Truncated)
+  }
+
+  let size = u32::from_ne_bytes(length) as usize;
+
+  if size > limit {
+    if io::copy(&mut reader.by_ref().take(size as u64), &mut io::sink())? < size as u64 {
+      return Err(FrameError::Truncated)
+    }
+
+    return Err(FrameError::TooLarge { size, limit })
+  }
+
+  let mut message = vec![0; size];
+
+  if fill(reader, &mut message)? < size {
+    return Err(FrameError::Truncated)
+  }
+
+  let message = std::str::from_utf8(&message).map_err(FrameError::InvalidUtf8)?;
+
+  serde_json::from_str(message).map_err(FrameError::InvalidJson)
+}
+
+// nothing is written if the message is too large, so the stream can still be used
+pub fn write_frame(writer: &mut impl Write, message: &impl Serialize, limit: usize) -> Result<(), FrameError> {
+  let message = serde_json::to_vec(message).map_err(FrameError::InvalidJson)?;
+
+  if message.len() > limit {
+    return Err(FrameError::TooLarge { size: message.len(), limit })
+  }
+
+  writer.write_all(&(message.len() as u32).to_ne_bytes())?;
+  writer.write_all(&message)?;
+  writer.flush()?;
+
+  Ok(())
+}
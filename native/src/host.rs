@@ -0,0 +1,920 @@
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::time::{Duration, Instant};
+use std::io::{Read, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, TryLockError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Receiver;
+use std::thread;
+use serde_json::Value;
+use crate::chunk;
+use crate::config::{Config, FilenamePolicy, HashAlgorithm, Settings};
+use crate::download;
+use crate::error::Error;
+use crate::framing;
+use crate::framing::FrameError;
+use crate::index::{Index, Progress};
+use crate::jobs::{Claim, Item, Job, Journal, State};
+use crate::picker::{Picked, Picker};
+
+// bumped whenever the extension needs to know about a change in messages or responses
+// 1 had no handshake, 2 added Hello and moved settings into the host config
+pub const PROTOCOL_VERSION: u32 = 2;
+
+// how many messages are handled at once
+const WORKERS: usize = 4;
+
+// how long the index of an archive directory is trusted before looking for changes made outside the host
+const RESCAN_INTERVAL: Duration = Duration::from_secs(60);
+
+// how often a message waiting on a job another host is working on checks whether it's done with it
+const CLAIM_INTERVAL: Duration = Duration::from_millis(100);
+
+// how often a download looks in the journal for whether its job has been dropped
+const DROPPED_INTERVAL: Duration = Duration::from_millis(500);
+
+// optional features the extension should check for before using them, even if the protocol version is new enough
+#[derive(Serialize)]
+pub struct Capabilities {
+  hashes: Vec<HashAlgorithm>,
+  // SetMany
+  batch: bool,
+  // progress events while scanning and downloading
+  progress: bool,
+  // ListJobs, RetryJob and DropJob
+  jobs: bool,
+}
+
+// how saving one of the items in a SetMany went
+#[derive(Serialize)]
+pub struct Saved {
+  hash: String,
+  // the folder it's archived in, whether it was just saved or already there
+  #[serde(skip_serializing_if="Option::is_none")]
+  name: Option<String>,
+  #[serde(skip_serializing_if="Option::is_none")]
+  error: Option<Error>,
+}
+
+// an item's hash, and the folder it's archived in or why it couldn't be
+type Outcome = (String, Result<String, Error>);
+
+// any message can also have an "id" field next to its variant, which is echoed back in every response to it
+#[derive(Deserialize)]
+pub enum Message {
+  // sent first so either side can tell when the other is out of date
+  Hello {
+    // only informational for now, every version so far can be answered
+    #[allow(dead_code)]
+    version: u32,
+    // sent by the extension's long-lived connection, so unfinished downloads aren't picked up by every short-lived host
+    #[serde(default)]
+    resume_jobs: bool,
+  },
+  // searches every configured root unless given a directory
+  Get {
+    #[serde(default)]
+    directory: Option<String>,
+    hashes: Vec<String>
+  },
+  // saves to the first configured root unless given a directory
+  Set {
+    #[serde(default)]
+    directory: Option<String>,
+    #[serde(flatten)]
+    item: Item,
+  },
+  // like Set for each item, answered once they've all been tried
+  SetMany {
+    #[serde(default)]
+    directory: Option<String>,
+    items: Vec<Item>,
+  },
+  Pick,
+  Ping,
+  GetConfig,
+  SetConfig(Settings),
+  // stops every message with this id that hasn't finished yet, answered as soon as it's read
+  Cancel {
+    id: Value,
+  },
+  // the journaled downloads in every configured root unless given a directory
+  ListJobs {
+    #[serde(default)]
+    directory: Option<String>,
+  },
+  // downloads a queued or failed job again, looking for it in every configured root unless given a directory
+  RetryJob {
+    #[serde(default)]
+    directory: Option<String>,
+    id: u64,
+  },
+  // forgets a job, stopping its download if it's running
+  DropJob {
+    #[serde(default)]
+    directory: Option<String>,
+    id: u64,
+  },
+}
+
+#[derive(Serialize)]
+#[serde(tag="type", rename_all="lowercase")]
+pub enum Response {
+  Get {
+    msg: HashMap<String, Option<String>>,
+  },
+  Suggestions {
+    msg: HashSet<String>,
+  },
+  Pick(Picked),
+  Hello {
+    version: u32,
+    capabilities: Capabilities,
+  },
+  Config {
+    msg: Settings,
+  },
+  Error(Error),
+  Pong,
+  #[serde(rename="set_many")]
+  SetMany {
+    msg: Vec<Saved>,
+  },
+  // whether anything with the id was still running
+  Cancel {
+    msg: bool,
+  },
+  // sent while an archive directory is being rescanned, before the response to the message that started it
+  Progress(Progress),
+  // every journaled download, by archive directory
+  Jobs {
+    msg: HashMap<String, Vec<Job>>,
+  },
+  // what became of a job after RetryJob or DropJob, done or dropped if it's no longer journaled
+  Job {
+    directory: String,
+    msg: Job,
+  },
+}
+
+impl Response {
+  // splits responses with a lot of hashes or folders into several that each fit
+  fn chunks(self, fits: impl Fn(&Response) -> bool) -> Vec<Response> {
+    match self {
+      Response::Get { msg } => chunk::split(msg.into_iter().collect(), &|items: &[(String, Option<String>)]|
+        fits(&Response::Get { msg: items.iter().cloned().collect() })
+      ).into_iter().map(|items|
+        Response::Get { msg: items.into_iter().collect() }
+      ).collect(),
+      Response::Suggestions { msg } => chunk::split(msg.into_iter().collect(), &|items: &[String]|
+        fits(&Response::Suggestions { msg: items.iter().cloned().collect() })
+      ).into_iter().map(|items|
+        Response::Suggestions { msg: items.into_iter().collect() }
+      ).collect(),
+      response => vec![response]
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct Reply<'a> {
+  #[serde(skip_serializing_if="Option::is_none")]
+  id: Option<&'a Value>,
+  // set on everything but the last message sent in reply to a message, including every part of a chunked response except the last
+  #[serde(skip_serializing_if="std::ops::Not::not")]
+  more: bool,
+  #[serde(flatten)]
+  response: &'a Response,
+}
+
+// responses are written by a single thread, in whatever order the workers finish them
+// the flag is set on the final response to a message, everything before it is marked with "more"
+type Outgoing = (Option<Value>, Response, bool);
+
+// writes a response, split into as many messages as it takes
+fn write_reply(writer: &mut impl Write, id: Option<&Value>, response: Response, last_response: bool) -> Result<(), FrameError> {
+  let chunks = response.chunks(|response|
+    serde_json::to_vec(&Reply { id, more: true, response }).is_ok_and(|bytes| bytes.len() <= chunk::MAX_MESSAGE_SIZE)
+  );
+  let last = chunks.len() - 1;
+
+  for (index, response) in chunks.iter().enumerate() {
+    let more = index < last || !last_response;
+
+    match framing::write_frame(writer, &Reply { id, more, response }, chunk::MAX_MESSAGE_SIZE) {
+      // a single folder name that doesn't fit is unlikely, but the extension should still hear about it, and about the chunks after it
+      Err(FrameError::TooLarge { size, .. }) => framing::write_frame(writer, &Reply {
+        id,
+        more,
+        response: &Response::Error(Error::Unavailable { reason: format!("The response is {} bytes, more than browsers accept", size) })
+      }, chunk::MAX_MESSAGE_SIZE)?,
+      written => written?
+    }
+  }
+
+  Ok(())
+}
+
+// keeps taking responses after the browser has gone away, so workers never block on a dead stream
+fn write_replies(mut writer: impl Write, outgoing: Receiver<Outgoing>) -> Result<(), FrameError> {
+  let mut failed = None;
+
+  for (id, response, last_response) in outgoing {
+    if failed.is_none() {
+      failed = write_reply(&mut writer, id.as_ref(), response, last_response).err();
+    }
+  }
+
+  match failed {
+    None | Some(FrameError::Closed) => Ok(()),
+    Some(error) => Err(error)
+  }
+}
+
+thread_local! {
+  // where the last panic on this thread happened, since catch_unwind only gets the payload
+  static PANIC_LOCATION: RefCell<Option<(String, u32)>> = const { RefCell::new(None) };
+}
+
+// install with panic::set_hook so panics while handling a message can be reported with their location
+pub fn record_panic(info: &panic::PanicHookInfo) {
+  PANIC_LOCATION.set(info.location().map(|location| (location.file().to_string(), location.line())));
+}
+
+// taken from https://github.com/neon64/chrome-native-messaging/blob/master/src/lib.rs#L130-L144
+fn panic_error(payload: Box<dyn std::any::Any + Send>) -> Error {
+  let msg = match payload.downcast_ref::<&'static str>() {
+    Some(s) => *s,
+    None => match payload.downcast_ref::<String>() {
+      Some(s) => &s[..],
+      None => "Box<Any>",
+    }
+  };
+
+  let location = PANIC_LOCATION.take();
+
+  Error::Panic {
+    reason: msg.to_string(),
+    file: location.as_ref().map(|(file, _)| file.to_string()),
+    line: location.map(|(_, line)| line),
+  }
+}
+
+// the directory the extension asked for, or every configured root
+fn directories(directory: Option<String>, settings: &Settings) -> Result<Vec<String>, Error> {
+  let directories = match directory {
+    Some(directory) => vec![directory],
+    None if settings.roots.is_empty() => return Err(Error::DirectoryMissing { path: None }),
+    None => settings.roots.clone()
+  };
+
+  match directories.iter().find(|directory| !Path::new(directory).is_dir()) {
+    Some(missing) => Err(Error::DirectoryMissing { path: Some(missing.to_string()) }),
+    None => Ok(directories)
+  }
+}
+
+// names from the extension become a single folder or file inside the archive directory, and nothing else
+fn component<'a>(directory: &str, name: &'a str) -> Result<&'a str, Error> {
+  let rejected = |reason: &str| Error::PathRejected {
+    path: Path::new(directory).join(name).display().to_string(),
+    reason: reason.to_string(),
+  };
+
+  let mut components = Path::new(name).components();
+
+  match (components.next(), components.next()) {
+    (Some(std::path::Component::Normal(_)), None) => Ok(name),
+    (None, _) => Err(rejected("the name is empty")),
+    _ => Err(rejected("the name has to be a single folder or file name"))
+  }
+}
+
+// an archive directory's index, and a lock held while rescanning it so only one worker does
+// the index itself is only locked briefly, so lookups can be answered from it while it's being rescanned
+// nothing waits for the rescan lock, workers that don't get it carry on with what's already known
+struct Archive {
+  directory: String,
+  index: Mutex<Index>,
+  rescan: Mutex<()>,
+  jobs: Journal,
+}
+
+impl Archive {
+  // a panic while rescanning leaves the index as it was before, which is still usable
+  fn index(&self) -> MutexGuard<'_, Index> {
+    self.index.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+  }
+
+  // waits for whichever host is working on the job to be done with it
+  fn claim(&self, id: u64, cancel: &AtomicBool) -> Result<Claim, Error> {
+    loop {
+      if let Some(claim) = self.jobs.claim(id).map_err(|err| Error::io(&self.jobs.path(), err))? {
+        return Ok(claim)
+      }
+
+      if cancel.load(Ordering::Relaxed) {
+        return Err(Error::Cancelled)
+      }
+
+      thread::sleep(CLAIM_INTERVAL);
+    }
+  }
+
+  fn is_stale(&self) -> bool {
+    self.index().is_stale(RESCAN_INTERVAL)
+  }
+
+  // the rescan to run if the directory hasn't been rescanned for a while, or none if it has or another worker is already at it
+  fn start_rescan(&self) -> Option<Rescan<'_>> {
+    let rescanning = match self.rescan.try_lock() {
+      Ok(rescanning) => rescanning,
+      Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+      Err(TryLockError::WouldBlock) => return None
+    };
+
+    // whoever held the lock before us may have just rescanned
+    if !self.is_stale() {
+      return None
+    }
+
+    Some(Rescan { archive: self, _rescanning: rescanning })
+  }
+
+  // rescans the directory if it hasn't been for a while and nothing else is
+  fn refresh(&self, report: &(dyn Fn(&Progress) + Sync), cancel: &AtomicBool) -> Result<(), Error> {
+    self.start_rescan().map_or(Ok(()), |rescan| rescan.run(report, cancel))
+  }
+}
+
+// held by the one worker rescanning an archive directory
+struct Rescan<'a> {
+  archive: &'a Archive,
+  _rescanning: MutexGuard<'a, ()>,
+}
+
+impl Rescan<'_> {
+  // when cancelled, whatever was hashed by then is kept and the rest is left for the next rescan
+  fn run(self, report: &(dyn Fn(&Progress) + Sync), cancel: &AtomicBool) -> Result<(), Error> {
+    let scan = self.archive.index().scan();
+    let changes = scan.run(report, cancel)?;
+    let complete = changes.is_complete();
+
+    let mut index = self.archive.index();
+    index.apply(changes);
+    index.save().ok();
+
+    if complete { Ok(()) } else { Err(Error::Cancelled) }
+  }
+}
+
+// answers messages from the browser, everything platform specific is passed in so it can be driven from tests
+pub struct Host {
+  // where settings are kept, or why there's nowhere to keep them
+  config_path: Result<PathBuf, String>,
+  picker: Box<dyn Picker>,
+  // kept for as long as the browser is connected
+  archives: Mutex<HashMap<String, Arc<Archive>>>,
+  // flags for messages that have been read but not answered yet, set to cancel them
+  running: Mutex<Vec<(Value, Arc<AtomicBool>)>>,
+}
+
+impl Host {
+  pub fn new(config_path: Result<PathBuf, String>, picker: Box<dyn Picker>) -> Host {
+    Host { config_path, picker, archives: Mutex::new(HashMap::new()), running: Mutex::new(Vec::new()) }
+  }
+
+  // handles messages until the browser closes the stream, only failing if the stream itself is broken
+  // messages are handled by a pool of workers so a slow rescan doesn't hold up everything sent after it
+  pub fn run(&self, mut reader: impl Read + Send, writer: impl Write + Send) -> Result<(), FrameError> {
+    let (replies, outgoing) = mpsc::channel::<Outgoing>();
+    let (jobs, pending) = mpsc::channel::<(Option<Value>, Message, Arc<AtomicBool>)>();
+    let pending = Mutex::new(pending);
+    // set once the browser's gone, so no more unfinished downloads are started
+    let stopping = AtomicBool::new(false);
+
+    thread::scope(|scope| {
+      let written = scope.spawn(move || write_replies(writer, outgoing));
+
+      for _ in 0..WORKERS {
+        let replies = replies.clone();
+        let pending = &pending;
+
+        scope.spawn(move || {
+          loop {
+            // the lock is only held while waiting, so the next worker can pick up a job while this one works
+            let job = pending.lock().unwrap().recv();
+            let Ok((id, message, cancel)) = job else { break };
+
+            // may have been cancelled while it was waiting for a worker
+            let result = if cancel.load(Ordering::Relaxed) {
+              Err(Error::Cancelled)
+            }
+            else {
+              panic::catch_unwind(panic::AssertUnwindSafe(||
+                self.handle(message, &|response| { replies.send((id.clone(), response, false)).ok(); }, &cancel)
+              )).unwrap_or_else(|payload| Err(panic_error(payload)))
+            };
+
+            self.finished(&cancel);
+            replies.send((id, result.unwrap_or_else(Response::Error), true)).ok();
+          }
+        });
+      }
+
+      let mut resuming = false;
+
+      let read = loop {
+        match framing::read_frame(&mut reader, framing::MAX_INCOMING_SIZE) {
+          Ok(mut message) => {
+            let id = message.as_object_mut().and_then(|object| object.remove("id"));
+
+            match serde_json::from_value::<Message>(message) {
+              // handled here rather than by a worker, since every worker may be busy with what it's cancelling
+              Ok(message @ Message::Cancel { .. }) => {
+                let result = self.handle(message, &|_| {}, &AtomicBool::new(false));
+                replies.send((id, result.unwrap_or_else(Response::Error), true)).unwrap()
+              },
+              Ok(message) => {
+                // on its own thread rather than a worker, so the messages sent after it aren't kept waiting
+                if !resuming && matches!(message, Message::Hello { resume_jobs: true, .. }) {
+                  resuming = true;
+                  scope.spawn(|| self.resume_jobs(&stopping));
+                }
+
+                jobs.send((id.clone(), message, self.started(id))).unwrap()
+              },
+              Err(error) => replies.send((id, Response::Error(Error::InvalidMessage { reason: error.to_string() }), true)).unwrap()
+            }
+          },
+          Err(FrameError::Closed) => break Ok(()),
+          Err(error) if error.is_recoverable() => {
+            replies.send((None, Response::Error(Error::InvalidMessage { reason: error.to_string() }), true)).unwrap()
+          },
+          Err(error) => break Err(error)
+        }
+      };
+
+      // lets the workers finish what they've started and then the writer send what they've answered
+      stopping.store(true, Ordering::Relaxed);
+      drop(jobs);
+      drop(replies);
+
+      let written = written.join().unwrap();
+      self.save_indexes();
+
+      read.and(written)
+    })
+  }
+
+  fn load_config(&self) -> Result<(PathBuf, Config), Error> {
+    let path = self.config_path.clone().map_err(|reason| Error::Unavailable { reason })?;
+
+    match Config::load(&path) {
+      Ok(config) => Ok((path, config)),
+      Err(err) => Err(Error::io(&path, err))
+    }
+  }
+
+  // starts from the archive directory's cache.json the first time it's used
+  fn archive(&self, directory: &str) -> Arc<Archive> {
+    self.archives.lock().unwrap().entry(directory.to_string()).or_insert_with(||
+      Arc::new(Archive {
+        directory: directory.to_string(),
+        index: Mutex::new(Index::load(Path::new(directory))),
+        rescan: Mutex::new(()),
+        jobs: Journal::load(Path::new(directory)),
+      })
+    ).clone()
+  }
+
+  // downloads whatever was left queued or running in the journals of the configured roots, one job at a time
+  // jobs another host is still working on are left to it, and a download that's been started is finished even if the browser goes away
+  fn resume_jobs(&self, stopping: &AtomicBool) {
+    let Ok((_, config)) = self.load_config() else { return };
+    let settings = config.settings;
+    let client = download::Client::new(&settings.downloads);
+    let cancel = AtomicBool::new(false);
+
+    for directory in settings.roots.iter().filter(|root| Path::new(root).is_dir()) {
+      let archive = self.archive(directory);
+      let unfinished = archive.jobs.unfinished();
+
+      if !unfinished.is_empty() {
+        archive.refresh(&|_| {}, &cancel).ok();
+      }
+
+      for id in unfinished {
+        if stopping.load(Ordering::Relaxed) {
+          return
+        }
+
+        let Ok(Some(claim)) = archive.jobs.claim(id) else { continue };
+        let Some(job) = archive.jobs.get(id) else { continue };
+
+        self.run_job(&archive, &settings, &client, claim, &job.item, &cancel).ok();
+      }
+    }
+  }
+
+  // the first of the directories with a job with the id in its journal
+  fn find_job(&self, directory: Option<String>, id: u64, settings: &Settings) -> Result<(String, Arc<Archive>), Error> {
+    directories(directory, settings)?.into_iter().map(|directory| {
+      let archive = self.archive(&directory);
+      (directory, archive)
+    }).find(|(_, archive)| archive.jobs.get(id).is_some()).ok_or_else(||
+      Error::InvalidMessage { reason: format!("There's no job {}", id) }
+    )
+  }
+
+  // a flag for a message that's about to be handled, which can only be cancelled if it has an id
+  fn started(&self, id: Option<Value>) -> Arc<AtomicBool> {
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    if let Some(id) = id {
+      self.running.lock().unwrap().push((id, cancel.clone()));
+    }
+
+    cancel
+  }
+
+  fn finished(&self, cancel: &Arc<AtomicBool>) {
+    self.running.lock().unwrap().retain(|(_, running)| !Arc::ptr_eq(running, cancel));
+  }
+
+  // returns whether anything was running with the id
+  fn cancel(&self, id: &Value) -> bool {
+    let running = self.running.lock().unwrap();
+    let mut cancelled = false;
+
+    for (_, cancel) in running.iter().filter(|(running, _)| running == id) {
+      cancel.store(true, Ordering::Relaxed);
+      cancelled = true;
+    }
+
+    cancelled
+  }
+
+  // writes out anything learned since the last rescan, so the next run starts from it
+  fn save_indexes(&self) {
+    for archive in self.archives.lock().unwrap().values() {
+      archive.index().save().ok();
+    }
+  }
+
+  // returns the final response, anything sent before it goes through send, which can be called from any thread
+  // long running messages check cancel as they go, and stop with Error::Cancelled once it's set
+  fn handle(&self, message: Message, send: &(dyn Fn(Response) + Sync), cancel: &AtomicBool) -> Result<Response, Error> {
+    match message {
+      Message::Ping => {
+        Ok(Response::Pong)
+      },
+      Message::Hello { .. } => {
+        Ok(Response::Hello {
+          version: PROTOCOL_VERSION,
+          capabilities: Capabilities {
+            hashes: HashAlgorithm::ALL.to_vec(),
+            batch: true,
+            progress: true,
+            jobs: true,
+          }
+        })
+      },
+      Message::Pick => {
+        self.picker.pick_folder("Pick archive location").map(|path|
+          Response::Pick(Picked::from(path))
+        ).map_err(|err|
+          Error::Unavailable { reason: format!("Unable to show a folder picker: {}", err) }
+        )
+      },
+      Message::Cancel { id } => {
+        Ok(Response::Cancel { msg: self.cancel(&id) })
+      },
+      Message::GetConfig => {
+        self.load_config().map(|(_, config)| Response::Config { msg: config.settings })
+      },
+      Message::SetConfig(settings) => {
+        self.load_config().and_then(|(path, mut config)| {
+          config.settings = settings;
+          config.save(&path).map_err(|err| Error::io(&path, err))?;
+
+          Ok(Response::Config { msg: config.settings })
+        })
+      },
+      // answers straight away from what's already known, then rescans and follows up with whatever changed
+      // archives another worker is already rescanning aren't waited for, that worker's message gets the follow up
+      Message::Get { directory, hashes } => {
+        let (_, config) = self.load_config()?;
+        let archives = directories(directory, &config.settings)?.iter().map(|directory| self.archive(directory)).collect::<Vec<_>>();
+
+        let look_up = || {
+          let mut names = HashMap::<String, Option<String>>::from_iter(hashes.iter().map(|hash| (hash.to_string(), None)));
+          let mut folders = HashSet::new();
+
+          for archive in &archives {
+            let index = archive.index();
+
+            // earlier roots win when a file has been archived more than once
+            for (hash, name) in names.iter_mut().filter(|(_, name)| name.is_none()) {
+              *name = index.names().get(hash).cloned();
+            }
+
+            folders.extend(index.folders());
+          }
+
+          (names, folders)
+        };
+
+        let (names, folders) = look_up();
+        let rescans = archives.iter().filter_map(|archive| archive.start_rescan()).collect::<Vec<_>>();
+
+        if rescans.is_empty() {
+          send(Response::Suggestions { msg: folders });
+
+          return Ok(Response::Get { msg: names })
+        }
+
+        send(Response::Suggestions { msg: folders.clone() });
+        send(Response::Get { msg: names.clone() });
+
+        for rescan in rescans {
+          rescan.run(&|progress| send(Response::Progress(progress.clone())), cancel)?;
+        }
+
+        let (refreshed_names, refreshed_folders) = look_up();
+        let new_folders = refreshed_folders.difference(&folders).cloned().collect::<HashSet<String>>();
+
+        if !new_folders.is_empty() {
+          send(Response::Suggestions { msg: new_folders });
+        }
+
+        Ok(Response::Get {
+          msg: refreshed_names.into_iter().filter(|(hash, name)| names.get(hash) != Some(name)).collect()
+        })
+      },
+      Message::Set { directory, item } => {
+        let hash = item.hash.clone();
+        let (_, saved) = self.save(directory, vec![item], send, cancel)?.remove(0);
+
+        Ok(Response::Get {
+          msg: HashMap::from([(hash, Some(saved?))])
+        })
+      },
+      Message::SetMany { directory, items } => {
+        let saved = self.save(directory, items, send, cancel)?;
+
+        // lets every tab know, the same as if each had been sent on its own
+        send(Response::Get {
+          msg: saved.iter().filter_map(|(hash, saved)| saved.as_ref().ok().map(|name| (hash.to_string(), Some(name.to_string())))).collect()
+        });
+
+        Ok(Response::SetMany {
+          msg: saved.into_iter().map(|(hash, saved)| match saved {
+            Ok(name) => Saved { hash, name: Some(name), error: None },
+            Err(error) => Saved { hash, name: None, error: Some(error) },
+          }).collect()
+        })
+      },
+      Message::ListJobs { directory } => {
+        let (_, config) = self.load_config()?;
+
+        Ok(Response::Jobs {
+          msg: directories(directory, &config.settings)?.into_iter().map(|directory| {
+            let jobs = self.archive(&directory).jobs.all();
+            (directory, jobs)
+          }).collect()
+        })
+      },
+      // answered with the job rather than an error when the download fails again, since failing is part of the job's state
+      Message::RetryJob { directory, id } => {
+        let (_, config) = self.load_config()?;
+        let settings = config.settings;
+        let (directory, archive) = self.find_job(directory, id, &settings)?;
+        let job = archive.jobs.get(id).ok_or_else(|| Error::InvalidMessage { reason: format!("There's no job {}", id) })?;
+        let claim = archive.claim(id, cancel)?;
+
+        archive.refresh(&|progress| send(Response::Progress(progress.clone())), cancel)?;
+
+        let client = download::Client::new(&settings.downloads);
+        let saved = self.run_job(&archive, &settings, &client, claim, &job.item, cancel);
+
+        let job = match saved {
+          Ok(name) => {
+            send(Response::Suggestions { msg: HashSet::from([name.to_string()]) });
+            send(Response::Get { msg: HashMap::from([(job.item.hash.to_string(), Some(name.to_string()))]) });
+
+            Job { state: State::Done { name }, partial: None, ..job }
+          },
+          Err(error @ Error::InvalidMessage { .. }) => return Err(error),
+          Err(_) => archive.jobs.get(id).unwrap_or(Job { state: State::Dropped, partial: None, ..job }),
+        };
+
+        Ok(Response::Job { directory, msg: job })
+      },
+      Message::DropJob { directory, id } => {
+        let (_, config) = self.load_config()?;
+        let (directory, archive) = self.find_job(directory, id, &config.settings)?;
+        let claim = archive.jobs.claim(id).map_err(|err| Error::io(&archive.jobs.path(), err))?;
+        let job = archive.jobs.remove(id).map_err(|err| Error::io(&archive.jobs.path(), err))?;
+
+        // a host that's downloading it removes what it's written so far once it notices
+        if claim.is_some() {
+          if let Some(partial) = job.as_ref().and_then(|job| job.partial.as_ref()) {
+            fs::remove_file(partial).ok();
+          }
+        }
+
+        drop(claim);
+
+        let job = job.ok_or_else(|| Error::InvalidMessage { reason: format!("There's no job {}", id) })?;
+
+        Ok(Response::Job { directory, msg: Job { state: State::Dropped, partial: None, ..job } })
+      },
+    }
+  }
+
+  // checks the items against what the archive directory's index already knows, then downloads whatever isn't archived yet one after another
+  // doesn't wait for a rescan, which a Get is there to start, since hashing a large archive can take much longer than the downloads
+  // fails outright only when the archive directory can't be used, otherwise says how each item went
+  fn save(&self, directory: Option<String>, items: Vec<Item>, send: &(dyn Fn(Response) + Sync), cancel: &AtomicBool) -> Result<Vec<Outcome>, Error> {
+    let (_, config) = self.load_config()?;
+    let settings = config.settings;
+    let directory = directories(directory, &settings)?.remove(0);
+
+    let archive = self.archive(&directory);
+
+    // shared by every item, so they all go through the same connections
+    let client = download::Client::new(&settings.downloads);
+
+    // every item is journaled before any are downloaded, so the ones not reached yet are picked up again if the host stops partway through
+    let queued = items.into_iter().map(|item| {
+      let queued = self.queue(&archive, &directory, &settings, item.clone());
+
+      (item.hash.clone(), item, queued)
+    }).collect::<Vec<_>>();
+
+    let saved = queued.into_iter().map(|(hash, item, queued)| {
+      let saved = match queued {
+        // another message or host is already downloading it, so this waits to be answered by however that goes
+        Ok(Some((id, claim))) => claim.map_or_else(|| archive.claim(id, cancel), Ok).and_then(|claim|
+          self.run_job(&archive, &settings, &client, claim, &item, cancel)
+        ),
+        Ok(None) => Ok(item.name),
+        Err(error) => Err(error)
+      };
+
+      (hash, saved)
+    }).collect::<Vec<_>>();
+
+    let names = saved.iter().filter_map(|(_, saved)| saved.as_ref().ok().cloned()).collect::<HashSet<String>>();
+
+    if !names.is_empty() {
+      send(Response::Suggestions { msg: names });
+    }
+
+    Ok(saved)
+  }
+
+  // journals an item, returning its job's id and the claim on it unless something else has it, or none if it's already archived in the folder it's meant to go in
+  // names that can't be saved are rejected before they're journaled, since trying again won't change them
+  fn queue(&self, archive: &Archive, directory: &str, settings: &Settings, item: Item) -> Result<Option<(u64, Option<Claim>)>, Error> {
+    if archive.index().names().get(&item.hash).is_some_and(|found_name| *found_name == item.name) {
+      return Ok(None)
+    }
+
+    let destination = Path::new(directory).join(component(directory, &item.name)?);
+    component(&destination.to_string_lossy(), filename(&item, settings))?;
+
+    let id = archive.jobs.add(item).map_err(|err| Error::io(&archive.jobs.path(), err))?;
+    let claim = archive.jobs.claim(id).map_err(|err| Error::io(&archive.jobs.path(), err))?;
+
+    Ok(Some((id, claim)))
+  }
+
+  // downloads a claimed job, returning the folder it's archived in
+  // the job is forgotten once it's archived, cancelled or dropped, and otherwise left in the journal as failed to be retried
+  fn run_job(&self, archive: &Archive, settings: &Settings, client: &download::Client, claim: Claim, item: &Item, cancel: &AtomicBool) -> Result<String, Error> {
+    let id = claim.id();
+    let journal_path = archive.jobs.path();
+
+    // whoever had the job before may have finished it, or the file may have been saved since it was queued
+    if archive.index().names().get(&item.hash).is_some_and(|found_name| *found_name == item.name) {
+      let job = archive.jobs.remove(id).map_err(|err| Error::io(&journal_path, err))?;
+
+      if let Some(partial) = job.and_then(|job| job.partial) {
+        fs::remove_file(partial).ok();
+      }
+
+      return Ok(item.name.to_string())
+    }
+
+    // dropped while it was waiting, or finished by another host without this one's index knowing yet
+    let job = archive.jobs.update(id, |job| job.state = State::Running).map_err(|err| Error::io(&journal_path, err))?;
+    let job = job.ok_or(Error::Cancelled)?;
+
+    // the journal is only looked at every so often, since the download checks whether it's been cancelled as every piece arrives
+    let (checked, dropped) = (Cell::new(Instant::now()), Cell::new(false));
+    let cancelled = || {
+      if !dropped.get() && checked.get().elapsed() >= DROPPED_INTERVAL {
+        checked.set(Instant::now());
+        dropped.set(archive.jobs.get(id).is_none());
+      }
+
+      cancel.load(Ordering::Relaxed) || dropped.get()
+    };
+
+    let mut temporary = None;
+    let saved = self.save_job(archive, settings, client, &job, &mut temporary, &cancelled);
+
+    // anything else that went wrong means what's been downloaded so far can't be trusted
+    let resumable = matches!(saved, Err(Error::Http { .. } | Error::Network { .. })) && !dropped.get();
+
+    if !resumable && saved.is_err() {
+      if let Some(temporary) = temporary {
+        fs::remove_file(temporary).ok();
+      }
+    }
+
+    match &saved {
+      Ok(_) | Err(Error::Cancelled) => archive.jobs.remove(id).map(|_| ()),
+      Err(error) => archive.jobs.update(id, |job| {
+        job.state = State::failed(error);
+        job.partial = job.partial.take().filter(|_| resumable);
+      }).map(|_| ())
+    }.ok();
+
+    saved
+  }
+
+  // downloads into the job's partial file, starting one if it doesn't have one yet, and files it away once it's been checked
+  // temporary is set to the file being downloaded into as soon as there is one, so it can be cleaned up however this ends
+  fn save_job(&self, archive: &Archive, settings: &Settings, client: &download::Client, job: &Job, temporary: &mut Option<PathBuf>, cancelled: &dyn Fn() -> bool) -> Result<String, Error> {
+    let Item { url, hash, name, .. } = &job.item;
+    let filename = filename(&job.item, settings);
+    let directory = &archive.directory;
+
+    if cancelled() {
+      return Err(Error::Cancelled)
+    }
+
+    let destination = Path::new(directory).join(component(directory, name)?);
+    fs::create_dir_all(&destination).map_err(|err| Error::io(&destination, err))?;
+
+    let requested_filename = destination.join(component(&destination.to_string_lossy(), filename)?);
+
+    let temporary: &Path = match job.partial.clone().filter(|partial| partial.is_file()) {
+      Some(partial) => temporary.insert(partial),
+      None => {
+        let partial = temporary.insert(download::temporary_file(&destination, filename).map_err(|err| Error::io(&destination, err))?);
+        archive.jobs.update(job.id, |job| job.partial = Some(partial.clone())).map_err(|err| Error::io(&archive.jobs.path(), err))?;
+
+        partial
+      }
+    };
+
+    // only renamed into the archive once it's been downloaded completely and checked
+    client.download(url, temporary, cancelled)?;
+
+    let size = fs::metadata(temporary).map(|metadata| metadata.len()).unwrap_or(0);
+
+    if let Some(max_file_size) = settings.limits.max_file_size.filter(|max_file_size| size > *max_file_size) {
+      return Err(Error::TooLarge {
+        path: requested_filename.display().to_string(),
+        size,
+        limit: max_file_size,
+      })
+    }
+
+    // a truncated or substituted download would otherwise be filed under the hash the page expected
+    // the job is left in the journal as failed with the hash it had, so it can be retried
+    let saved_hash = File::open(temporary).and_then(crate::base64_hash).map_err(|err| Error::io(temporary, err))?;
+
+    if saved_hash != *hash {
+      return Err(Error::HashMismatch {
+        path: requested_filename.display().to_string(),
+        expected: hash.to_string(),
+        actual: saved_hash,
+      })
+    }
+
+    let saved = download::persist(temporary, &requested_filename).map_err(|err| Error::io(&requested_filename, err))?;
+    let saved_filename = saved.file_name().and_then(|filename| filename.to_str()).unwrap_or(filename);
+
+    archive.index().insert(name, saved_filename, hash);
+
+    Ok(name.to_string())
+  }
+}
+
+// the filename the item is saved as, going by the filename policy
+fn filename<'a>(item: &'a Item, settings: &Settings) -> &'a str {
+  match (settings.filename, &item.original_filename) {
+    (FilenamePolicy::Original, Some(original_filename)) => original_filename,
+    _ => &item.filename
+  }
+}
@@ -0,0 +1,273 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant, SystemTime};
+use crate::error::Error;
+
+pub const CACHE_PATH: &str = "cache.json";
+
+// how often progress is reported while hashing, besides when it starts and finishes
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+// which folder every file in an archive directory is in, by hash
+// kept in memory for as long as the host runs, and written back to cache.json in the archive directory
+pub struct Index {
+  directory: PathBuf,
+  // folder -> file -> hash, the same shape as cache.json
+  cache: HashMap<String, HashMap<String, String>>,
+  // hash -> folder, rebuilt whenever the cache changes
+  names: HashMap<String, String>,
+  // folders changed after this are rescanned
+  as_of: Option<SystemTime>,
+  // when this process last rescanned, if it has yet
+  refreshed: Option<Instant>,
+  // whether the cache has changed since it was last written
+  dirty: bool,
+}
+
+impl Index {
+  // starts from cache.json, which may be out of date, missing or unreadable
+  pub fn load(directory: &Path) -> Index {
+    let (cache, as_of): (HashMap<String, HashMap<String, String>>, Option<SystemTime>) = File::open(directory.join(CACHE_PATH)).map(|cache|
+      (
+        serde_json::from_reader(BufReader::new(&cache)).unwrap_or_default(),
+        cache.metadata().and_then(|metadata| metadata.modified()).ok()
+      )
+    ).unwrap_or_default();
+
+    let mut index = Index { directory: directory.to_path_buf(), cache, names: HashMap::new(), as_of, refreshed: None, dirty: false };
+    index.rebuild_names();
+
+    index
+  }
+
+  fn rebuild_names(&mut self) {
+    self.names = self.cache.iter().flat_map(|(subdirectory, files)|
+      files.values().map(|hash|
+        (hash.to_string(), subdirectory.to_string())
+      )
+    ).collect();
+  }
+
+  // whether it's been longer than max_age since this process last looked for changes on disk
+  pub fn is_stale(&self, max_age: Duration) -> bool {
+    self.refreshed.is_none_or(|refreshed| refreshed.elapsed() > max_age)
+  }
+
+  // hashes files in folders that have changed since the last scan
+  pub fn refresh(&mut self) -> Result<(), Error> {
+    let changes = self.scan().run(&|_| {}, &AtomicBool::new(false))?;
+    self.apply(changes);
+
+    Ok(())
+  }
+
+  // a copy of what a rescan needs, so it can run without holding on to the index
+  pub fn scan(&self) -> Scan {
+    Scan { directory: self.directory.clone(), cache: self.cache.clone(), as_of: self.as_of }
+  }
+
+  // files saved with insert while the scan was running are kept, since the scan only removes files it knew about
+  // a cancelled scan's changes are kept too, but the index stays stale so the rest is picked up next time
+  pub fn apply(&mut self, changes: Changes) {
+    let Changes { changes, started, complete } = changes;
+
+    for (subdirectory, change) in &changes {
+      match change {
+        Some(files) => {
+          let cached_files = self.cache.entry(subdirectory.to_string()).or_default();
+
+          for (file, change) in files {
+            match change {
+              Some(hash) => cached_files.insert(file.to_string(), hash.to_string()),
+              None => cached_files.remove(file)
+            };
+          }
+        },
+        None => {
+          self.cache.remove(subdirectory);
+        }
+      }
+    }
+
+    if !changes.is_empty() {
+      self.dirty = true;
+      self.rebuild_names();
+    }
+
+    if complete {
+      self.as_of = Some(started);
+      self.refreshed = Some(Instant::now());
+    }
+  }
+
+  // records a file saved by the host, without rescanning its folder
+  pub fn insert(&mut self, subdirectory: &str, file: &str, hash: &str) {
+    self.cache.entry(subdirectory.to_string()).or_default().insert(file.to_string(), hash.to_string());
+    self.names.insert(hash.to_string(), subdirectory.to_string());
+    self.dirty = true;
+  }
+
+  pub fn names(&self) -> &HashMap<String, String> {
+    &self.names
+  }
+
+  // every folder with at least one file in it
+  pub fn folders(&self) -> HashSet<String> {
+    self.cache.iter().filter(|(_, files)| !files.is_empty()).map(|(subdirectory, _)| subdirectory.to_string()).collect()
+  }
+
+  // writes cache.json, if anything has changed since it was last written
+  // its modification time is when the cache was last complete, which is what load reads back as as_of
+  pub fn save(&mut self) -> Result<(), io::Error> {
+    if self.dirty {
+      let file = File::create(self.directory.join(CACHE_PATH))?;
+      let mut writer = BufWriter::new(&file);
+      serde_json::to_writer(&mut writer, &self.cache)?;
+      writer.flush()?;
+      drop(writer);
+
+      // a cache that has never been complete is older than everything, so every folder gets looked at next time
+      file.set_modified(self.as_of.unwrap_or(SystemTime::UNIX_EPOCH))?;
+      self.dirty = false;
+    }
+
+    Ok(())
+  }
+}
+
+pub struct Scan {
+  directory: PathBuf,
+  cache: HashMap<String, HashMap<String, String>>,
+  as_of: Option<SystemTime>,
+}
+
+pub struct Changes {
+  changes: crate::CacheChanges,
+  // anything changed while scanning will be newer than this, and picked up next time
+  started: SystemTime,
+  // false if the scan was cancelled before it got through every folder
+  complete: bool,
+}
+
+impl Changes {
+  pub fn is_complete(&self) -> bool {
+    self.complete
+  }
+}
+
+impl Scan {
+  // report is called from whichever threads are hashing, and setting cancel stops hashing after the files already started
+  pub fn run(self, report: &(dyn Fn(&Progress) + Sync), cancel: &AtomicBool) -> Result<Changes, Error> {
+    let started = SystemTime::now();
+    let (changes, complete) = crate::update_cache_with_progress(&self.cache, &self.as_of, &self.directory, report, cancel)?;
+
+    Ok(Changes { changes, started, complete })
+  }
+}
+
+// how far along hashing the new files in an archive directory is
+#[derive(Clone, Debug, Serialize)]
+pub struct Progress {
+  pub directory: String,
+  // the folder being hashed, none until hashing starts
+  pub subfolder: Option<String>,
+  pub files_discovered: u64,
+  pub files_hashed: u64,
+  pub bytes_discovered: u64,
+  pub bytes_hashed: u64,
+  // estimated from how fast files have been hashed so far
+  pub eta_seconds: Option<u64>,
+}
+
+impl fmt::Display for Progress {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}/{} files, {}/{} MB",
+      self.files_hashed,
+      self.files_discovered,
+      self.bytes_hashed / (1024 * 1024),
+      self.bytes_discovered / (1024 * 1024)
+    )?;
+
+    if let Some(subfolder) = &self.subfolder {
+      write!(f, " in {}", subfolder)?;
+    }
+
+    match self.eta_seconds {
+      Some(eta_seconds) if self.files_hashed < self.files_discovered => write!(f, ", about {}s left", eta_seconds),
+      _ => Ok(())
+    }
+  }
+}
+
+// counts hashed files from any thread, reporting at most every PROGRESS_INTERVAL
+// nothing is reported when there's nothing to hash, so quick rescans stay quiet
+pub(crate) struct Tracker<'a> {
+  progress: Mutex<(Progress, Instant)>,
+  started: Instant,
+  report: &'a (dyn Fn(&Progress) + Sync),
+}
+
+impl<'a> Tracker<'a> {
+  // reports straight away, so there's something to show before the first file is hashed
+  pub(crate) fn new(directory: &Path, sizes: impl Iterator<Item=u64>, report: &'a (dyn Fn(&Progress) + Sync)) -> Tracker<'a> {
+    let (files_discovered, bytes_discovered) = sizes.fold((0, 0), |(files, bytes), size| (files + 1, bytes + size));
+
+    let progress = Progress {
+      directory: directory.display().to_string(),
+      subfolder: None,
+      files_discovered,
+      files_hashed: 0,
+      bytes_discovered,
+      bytes_hashed: 0,
+      eta_seconds: None,
+    };
+
+    if files_discovered > 0 {
+      report(&progress);
+    }
+
+    Tracker { progress: Mutex::new((progress, Instant::now())), started: Instant::now(), report }
+  }
+
+  pub(crate) fn start(&self, subfolder: &str) {
+    self.update(false, |progress| progress.subfolder = Some(subfolder.to_string()));
+  }
+
+  pub(crate) fn hashed(&self, size: u64) {
+    self.update(false, |progress| {
+      progress.files_hashed += 1;
+      progress.bytes_hashed += size;
+    });
+  }
+
+  pub(crate) fn finish(&self) {
+    self.update(true, |_| {});
+  }
+
+  fn update(&self, force: bool, change: impl FnOnce(&mut Progress)) {
+    let mut guard = self.progress.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let (progress, reported) = &mut *guard;
+
+    change(progress);
+
+    if progress.files_discovered == 0 || !(force || reported.elapsed() >= PROGRESS_INTERVAL) {
+      return
+    }
+
+    let elapsed = self.started.elapsed().as_secs_f64();
+
+    progress.eta_seconds = (progress.bytes_hashed > 0).then(||
+      ((progress.bytes_discovered - progress.bytes_hashed) as f64 * elapsed / progress.bytes_hashed as f64).ceil() as u64
+    );
+
+    (self.report)(progress);
+    *reported = Instant::now();
+  }
+}
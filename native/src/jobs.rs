@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use crate::error::Error;
+
+pub const JOBS_PATH: &str = "jobs.json";
+
+// held while jobs.json is read and rewritten, so hosts running at the same time don't undo each other's changes
+const JOBS_LOCK_PATH: &str = ".jobs.lock";
+
+// written next to jobs.json and renamed over it, so a host that's killed partway through saving doesn't lose the journal
+const JOBS_TEMPORARY_PATH: &str = ".jobs.json.part";
+
+// a file to download into the archive directory, and the folder to put it in
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Item {
+  pub url: String,
+  pub hash: String,
+  pub name: String,
+  pub filename: String,
+  // used instead of filename when the filename policy says so
+  #[serde(default, skip_serializing_if="Option::is_none")]
+  pub original_filename: Option<String>,
+}
+
+// only queued, running and failed jobs are journaled, done and dropped are for telling the extension what became of one
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag="state", rename_all="lowercase")]
+pub enum State {
+  Queued,
+  Running,
+  // the code and message of the error it failed with, which stays in the journal until it's retried or dropped
+  Failed {
+    code: String,
+    error: String,
+  },
+  Done {
+    name: String,
+  },
+  Dropped,
+}
+
+impl State {
+  pub fn failed(error: &Error) -> State {
+    State::Failed { code: error.code().to_string(), error: error.to_string() }
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Job {
+  // only unique within an archive directory
+  pub id: u64,
+  #[serde(flatten)]
+  pub item: Item,
+  // seconds since the unix epoch
+  pub added: u64,
+  #[serde(flatten)]
+  pub state: State,
+  // what's been downloaded so far, kept after network errors so the next attempt can pick up from it
+  #[serde(default, skip_serializing_if="Option::is_none")]
+  pub partial: Option<PathBuf>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Saved {
+  next_id: u64,
+  jobs: Vec<Job>,
+}
+
+// downloads into an archive directory that haven't finished, kept in jobs.json so they outlive the host
+// every host on the machine may be using it at once, so it's read fresh from disk whenever it's looked at or changed
+pub struct Journal {
+  directory: PathBuf,
+}
+
+impl Journal {
+  pub fn load(directory: &Path) -> Journal {
+    Journal { directory: directory.to_path_buf() }
+  }
+
+  pub fn path(&self) -> PathBuf {
+    self.directory.join(JOBS_PATH)
+  }
+
+  // an unreadable journal is treated as empty, the same as a missing one
+  pub fn all(&self) -> Vec<Job> {
+    self.locked(|saved| (saved.jobs.clone(), false)).unwrap_or_default()
+  }
+
+  pub fn get(&self, id: u64) -> Option<Job> {
+    self.all().into_iter().find(|job| job.id == id)
+  }
+
+  // jobs that were queued or downloading when the host that added them stopped, or that it's still working on
+  pub fn unfinished(&self) -> Vec<u64> {
+    self.all().into_iter().filter(|job| matches!(job.state, State::Queued | State::Running)).map(|job| job.id).collect()
+  }
+
+  // queues an item, returning the job's id
+  // an item that's already journaled for the same folder is queued again under its old id, keeping whatever it had downloaded
+  pub fn add(&self, item: Item) -> Result<u64, io::Error> {
+    self.locked(|saved| {
+      if let Some(job) = saved.jobs.iter_mut().find(|job| job.item.hash == item.hash && job.item.name == item.name) {
+        job.item = item;
+
+        if job.state != State::Running {
+          job.state = State::Queued;
+        }
+
+        return (job.id, true)
+      }
+
+      let id = saved.next_id;
+
+      saved.next_id += 1;
+      saved.jobs.push(Job {
+        id,
+        item,
+        added: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0),
+        state: State::Queued,
+        partial: None,
+      });
+
+      (id, true)
+    })
+  }
+
+  // returns the job as it is after the change, or none if there's no job with the id
+  pub fn update(&self, id: u64, change: impl FnOnce(&mut Job)) -> Result<Option<Job>, io::Error> {
+    self.locked(|saved| match saved.jobs.iter_mut().find(|job| job.id == id) {
+      Some(job) => {
+        change(job);
+        (Some(job.clone()), true)
+      },
+      None => (None, false)
+    })
+  }
+
+  pub fn remove(&self, id: u64) -> Result<Option<Job>, io::Error> {
+    self.locked(|saved| match saved.jobs.iter().position(|job| job.id == id) {
+      Some(position) => (Some(saved.jobs.remove(position)), true),
+      None => (None, false)
+    })
+  }
+
+  // takes the job for this process, or none if some host is already working on it
+  // held for as long as the claim is, and given up by the operating system if the host stops without letting go
+  pub fn claim(&self, id: u64) -> Result<Option<Claim>, io::Error> {
+    let path = self.directory.join(format!(".jobs.{}.lock", id));
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(&path)?;
+
+    match file.try_lock() {
+      Ok(()) => Ok(Some(Claim { id, file: Some(file), path, journal: Journal::load(&self.directory) })),
+      Err(TryLockError::WouldBlock) => Ok(None),
+      Err(TryLockError::Error(err)) => Err(err)
+    }
+  }
+
+  // runs change on what's in jobs.json, writing it back if change says it's changed anything
+  // kept even once it's empty, so ids aren't reused for different jobs
+  fn locked<T>(&self, change: impl FnOnce(&mut Saved) -> (T, bool)) -> Result<T, io::Error> {
+    let lock = OpenOptions::new().write(true).create(true).truncate(false).open(self.directory.join(JOBS_LOCK_PATH))?;
+    lock.lock()?;
+
+    let path = self.path();
+    let mut saved: Saved = File::open(&path).ok().and_then(|file|
+      serde_json::from_reader(BufReader::new(file)).ok()
+    ).unwrap_or_default();
+
+    let (result, changed) = change(&mut saved);
+
+    if changed {
+      let temporary = self.directory.join(JOBS_TEMPORARY_PATH);
+      let mut writer = BufWriter::new(File::create(&temporary)?);
+      serde_json::to_writer_pretty(&mut writer, &saved)?;
+      writer.flush()?;
+      drop(writer);
+
+      fs::rename(&temporary, &path)?;
+    }
+
+    Ok(result)
+  }
+}
+
+// a job this process has taken, which no other host picks up until it's dropped
+pub struct Claim {
+  id: u64,
+  file: Option<File>,
+  path: PathBuf,
+  journal: Journal,
+}
+
+impl Claim {
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+}
+
+// the lock file is only removed once the job's gone from the journal, so a job that's still there is never held by two hosts at once
+impl Drop for Claim {
+  fn drop(&mut self) {
+    drop(self.file.take());
+
+    if self.journal.get(self.id).is_none() {
+      fs::remove_file(&self.path).ok();
+    }
+  }
+}
@@ -1,29 +1,51 @@
 use rayon::prelude::*;
-use std::io::BufReader;
-use std::io::BufWriter;
 use std::path::Path;
 use std::time;
+use std::io;
 use std::io::Read;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs::File;
+use std::sync::atomic::{AtomicBool, Ordering};
 use md5::Digest;
-
+use error::Error;
+
+pub mod chunk;
+pub mod config;
+pub mod download;
+pub mod error;
+pub mod framing;
+pub mod host;
+pub mod index;
+pub mod jobs;
+pub mod picker;
+
+// folder -> file -> new hash, where None means the folder or file has gone
+pub type CacheChanges = HashMap<String, Option<HashMap<String, Option<String>>>>;
+
+// a directory that can't be read has no changes to report
 pub fn update_cache(cache: &HashMap<String, HashMap<String, String>>, cache_as_of: &Option<time::SystemTime>, directory: &Path) -> HashMap<String, Option<HashMap<String, Option<String>>>> {
+  update_cache_with_progress(cache, cache_as_of, directory, &|_| {}, &AtomicBool::new(false)).map(|(changes, _)| changes).unwrap_or_default()
+}
+
+// like update_cache, calling report every so often while files are being hashed, and stopping early once cancel is set
+// also returns whether every folder was looked at, since the changes then leave out the folders that weren't
+// folders and files that are deleted while they're being scanned are treated as removed, and names that aren't UTF-8 are skipped
+pub fn update_cache_with_progress(cache: &HashMap<String, HashMap<String, String>>, cache_as_of: &Option<time::SystemTime>, directory: &Path, report: &(dyn Fn(&index::Progress) + Sync), cancel: &AtomicBool) -> Result<(CacheChanges, bool), Error> {
   let mut changes = HashMap::<String, Option<HashMap<String, Option<String>>>>::new();
 
   let directory_modified = directory.metadata().ok().and_then(|metadata|
     metadata.modified().ok()
-  ).zip(*cache_as_of).map_or(true, |(modified, cache_modified)|
+  ).zip(*cache_as_of).is_none_or(|(modified, cache_modified)|
     modified > cache_modified
   );
 
   let subdirectories = if directory_modified {
-    let subdirectories: HashSet<String> = directory.read_dir().unwrap().filter_map(|child| child.ok()).filter(|child|
-      child.file_type().map_or(false, |file_type|
+    let subdirectories: HashSet<String> = directory.read_dir().map_err(|err| Error::io(directory, err))?.filter_map(|child| child.ok()).filter(|child|
+      child.file_type().is_ok_and(|file_type|
         file_type.is_dir()
       )
-    ).map(|child| child.file_name().into_string().unwrap()).collect();
+    ).filter_map(|child| child.file_name().into_string().ok()).collect();
     
     subdirectories.iter().for_each(|subdirectory| {
       if !cache.contains_key(subdirectory) {
@@ -43,16 +65,29 @@ pub fn update_cache(cache: &HashMap<String, HashMap<String, String>>, cache_as_o
     cache.keys().cloned().collect()
   };
 
+  // everything to hash is found first, so progress can be reported against a total
+  let mut unhashed = Vec::<(String, Vec<(String, u64)>)>::new();
+
   for subdirectory in subdirectories {
     let subdirectory_path = directory.join(&subdirectory);
 
-    let subdirectory_modified = subdirectory_path.metadata().ok().and_then(|metadata|
+    // a folder moved in from elsewhere keeps its old modification time, but still has to be scanned
+    let subdirectory_modified = !cache.contains_key(&subdirectory) || subdirectory_path.metadata().ok().and_then(|metadata|
       metadata.modified().ok()
-    ).zip(*cache_as_of).map_or(true, |(modified, cache_modified)|
+    ).zip(*cache_as_of).is_none_or(|(modified, cache_modified)|
       modified > cache_modified
     );
 
     if subdirectory_modified {
+      let children = match subdirectory_path.read_dir() {
+        Ok(children) => children,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+          changes.insert(subdirectory.to_string(), None);
+          continue
+        },
+        Err(err) => return Err(Error::io(&subdirectory_path, err))
+      };
+
       let default = HashMap::<String, String>::new();
       let cached_files = cache.get(&subdirectory).unwrap_or(&default);
 
@@ -62,87 +97,107 @@ pub fn update_cache(cache: &HashMap<String, HashMap<String, String>>, cache_as_o
 
       let subdirectory_changes = changes.get_mut(&subdirectory).unwrap().as_mut().unwrap();
 
-      let files: HashSet<String> = subdirectory_path.read_dir().unwrap().filter_map(|child| child.ok()).filter(|child|
-        child.file_type().map_or(false, |file_type|
+      let files: HashSet<String> = children.filter_map(|child| child.ok()).filter(|child|
+        child.file_type().is_ok_and(|file_type|
           file_type.is_file()
         )
-      ).map(|child| child.file_name().into_string().unwrap()).collect();
-
-      files.par_iter().filter(|file|
-        !cached_files.contains_key(*file)
-      ).map(|file|
-        (file, base64_hash(File::open(subdirectory_path.join(file)).unwrap()))
-      ).collect::<Vec<_>>().iter().for_each(|(file, hash)| {
-        subdirectory_changes.insert(file.to_string(), Some(hash.to_string()));
-      });
+      ).filter_map(|child| child.file_name().into_string().ok()).filter(|file|
+        // still being downloaded, or left behind by a host that was killed partway through
+        !download::is_temporary(file)
+      ).collect();
 
       cached_files.iter().for_each(|(file, _)| {
         if !files.contains(file) {
           subdirectory_changes.insert(file.to_string(), None);
         }
       });
+
+      let new_files: Vec<(String, u64)> = files.into_iter().filter(|file|
+        !cached_files.contains_key(file)
+      ).map(|file| {
+        let size = subdirectory_path.join(&file).metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        (file, size)
+      }).collect();
+
+      if !new_files.is_empty() {
+        unhashed.push((subdirectory, new_files));
+      }
     }
   }
 
-  changes
-}
+  let progress = index::Tracker::new(directory, unhashed.iter().flat_map(|(_, files)| files.iter().map(|(_, size)| *size)), report);
 
-pub fn hash_files(directory: &String) -> Result<HashMap<String, String>, String> {
-  let cache_path = Path::new(&directory).join("cache.json");
-
-  let (mut cache, cache_as_of): (HashMap<String, HashMap<String, String>>, Option<time::SystemTime>) = File::open(&cache_path).map(|cache|
-    (
-      serde_json::from_reader(BufReader::new(&cache)).unwrap_or_default(),
-      cache.metadata().and_then(|metadata| metadata.modified()).ok()
-    )
-  ).unwrap_or_default();
-
-  update_cache(&cache, &cache_as_of, Path::new(&directory)).iter().for_each(|(subdirectory, change)| {
-    match change {
-      Some(value) => {
-        if !cache.contains_key(subdirectory) {
-          cache.insert(subdirectory.to_string(), HashMap::new());
-        }
+  let mut unhashed = unhashed.into_iter();
+  let mut complete = true;
+
+  for (subdirectory, files) in unhashed.by_ref() {
+    let subdirectory_path = directory.join(&subdirectory);
+
+    progress.start(&subdirectory);
 
-        value.iter().for_each(|(file, change)| {
-          match change {
-            Some(hash) => {
-              cache.get_mut(subdirectory).unwrap().insert(file.to_string(), hash.to_string());
-            },
-            None => {
-              cache.get_mut(subdirectory).unwrap().remove(file);
-            }
+    let hashes = files.par_iter().map(|(file, size)| {
+      if cancel.load(Ordering::Relaxed) {
+        return Ok(None)
+      }
+
+      let path = subdirectory_path.join(file);
+
+      // deleted since the folder was listed, so there's nothing to add
+      let hash = match File::open(&path).and_then(base64_hash) {
+        Ok(hash) => Some(hash),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(Error::io(&path, err))
+      };
+
+      progress.hashed(*size);
+      Ok(Some((file, hash)))
+    }).collect::<Result<Option<Vec<_>>, Error>>()?;
+
+    match hashes {
+      Some(hashes) => {
+        let subdirectory_changes = changes.get_mut(&subdirectory).unwrap().as_mut().unwrap();
+
+        hashes.iter().for_each(|(file, hash)| {
+          if let Some(hash) = hash {
+            subdirectory_changes.insert(file.to_string(), Some(hash.to_string()));
           }
         });
       },
       None => {
-        cache.remove(subdirectory);
+        changes.remove(&subdirectory);
+        complete = false;
+        break;
       }
     }
-  });
+  }
 
-  File::create(cache_path).ok().map(|cache_file|
-    serde_json::to_writer(BufWriter::new(cache_file), &cache)
-  );
+  // folders that weren't hashed are left out entirely, so the next scan finds them again
+  for (subdirectory, _) in unhashed {
+    changes.remove(&subdirectory);
+  }
 
-  Ok(
-    cache.iter().map(|(subdirectory, files)|
-      files.iter().map(|(_, hash)|
-        (hash.to_string(), subdirectory.to_string())
-      )
-    ).flatten().collect::<HashMap<String, String>>()
-  )
+  progress.finish();
+
+  Ok((changes, complete))
+}
+
+pub fn hash_files(directory: &String) -> Result<HashMap<String, String>, String> {
+  let mut index = index::Index::load(Path::new(&directory));
+  index.refresh().map_err(|err| err.to_string())?;
+  index.save().ok();
+
+  Ok(index.names().clone())
 }
 
-fn base64_hash(mut file: File) -> String {
+pub fn base64_hash(mut file: File) -> Result<String, io::Error> {
   let mut digest = md5::Md5::new();
   let mut buffer = [0; 64 * 1024];
-  let mut bytes_read = file.read(&mut buffer).unwrap();
+  let mut bytes_read = file.read(&mut buffer)?;
 
   while bytes_read > 0 {
     digest.update(&buffer[0..bytes_read]);
-    bytes_read = file.read(&mut buffer).unwrap();
+    bytes_read = file.read(&mut buffer)?;
   }
 
-  base64::encode(digest.finalize())
+  Ok(base64::encode(digest.finalize()))
 }
@@ -1,26 +1,20 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use chrome_native_messaging::{read_input, send_message, Error};
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::sync::Arc;
-use std::path::Path;
-use std::path::PathBuf;
+use serde::Deserialize;
 use std::time;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::process;
 use std::io;
 use std::panic;
-use exitcode;
-use downloader::verify::Verification;
-use downloader::Downloader;
-use downloader::Download;
-use wfd;
-use winapi::um::winuser;
-use user32;
+use archive::host;
+use archive::host::Host;
+use platform::Native;
+use platform::Platform;
 
+mod cli;
+mod doctor;
 mod extension;
-mod lib;
+mod platform;
 
 #[allow(dead_code)]
 #[derive(Deserialize)]
@@ -29,243 +23,87 @@ struct Cache {
   hashes: HashMap<String, String>
 }
 
-#[derive(Deserialize)]
-enum Message {
-  Get {
-    directory: String,
-    hashes: Vec<String>
-  },
-  Set {
-    directory: String,
-    url: String,
-    hash: String,
-    name: String,
-    filename: String,
-  },
-  Pick,
-}
-
-#[derive(Serialize)]
-#[serde(tag="type", rename_all="lowercase")]
-enum Response {
-  Get {
-    msg: HashMap<String, Option<String>>,
-  },
-  Suggestions {
-    msg: HashSet<String>,
-  },
-  Pick {
-    msg: String,
-  },
-  Error {
-    error: String,
-  },
-}
-
-// vulnerable to a race condition since we can't pass in a file to downloader.download()
-fn unique_filename(filename: &Path) -> Option<PathBuf> {
-  let stem = filename.file_stem()?.to_str()?;
-  let extension = filename.extension().and_then(|extension|
-    extension.to_str()
-  ).map(|extension|
-    format!(".{}", extension)
-  ).unwrap_or("".to_string());
-
-  let mut final_filename = filename.clone().to_path_buf();
-  let mut number: u16 = 0;
+// prints to the console when there is one, and falls back to a message box when double-clicked
+fn report(result: Result<String, String>, console: bool) -> exitcode::ExitCode {
+  let (message, error, exit_code) = match result {
+    Ok(message) => (message, false, exitcode::OK),
+    Err(message) => (message, true, exitcode::IOERR)
+  };
 
-  while final_filename.exists() {
-    number += 1;
-    final_filename.set_file_name(format!("{} ({}){}", stem, number, extension));
+  if console {
+    if error {
+      eprintln!("{}", message);
+    }
+    else {
+      println!("{}", message);
+    }
+  }
+  else {
+    Native::message_box("Archiver", &message, error);
   }
 
-  Some(final_filename)
+  exit_code
 }
 
-// taken from https://github.com/neon64/chrome-native-messaging/blob/master/src/lib.rs#L130-L144
-fn handle_panic(info: &std::panic::PanicInfo) {
-  let msg = match info.payload().downcast_ref::<&'static str>() {
-    Some(s) => *s,
-    None => match info.payload().downcast_ref::<String>() {
-      Some(s) => &s[..],
-      None => "Box<Any>",
-    }
-  };
+// reports the output of a command that checks something, exiting unsuccessfully if the check didn't pass
+fn report_with(command: impl FnOnce(&mut Vec<u8>) -> Result<bool, String>) -> exitcode::ExitCode {
+  let console = Native::attach_console();
+  let mut out = Vec::new();
 
-  send_message(
-    io::stdout(),
-    &Response::Error {
-      error: format!(
-        "Panic:\n{}\n{}\n{}",
-        msg,
-        info.location().map_or("", |l| l.file()),
-        info.location().map_or("".to_string(), |l| format!("{}", l.line()))
-      )
-    }
-  ).unwrap();
-}
+  match command(&mut out) {
+    Ok(passed) => {
+      report(Ok(String::from_utf8_lossy(&out).trim_end().to_string()), console);
 
-fn main() {
-  unsafe {
-    winuser::SetProcessDpiAwarenessContext(
-      winapi::shared::windef::DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2
-    );
+      if passed { exitcode::OK } else { exitcode::UNAVAILABLE }
+    },
+    Err(message) => report(Err(message), console)
   }
+}
 
-  if std::env::args().len() == 1 {
-    let install_result = extension::is_installed(extension::NAME).map_err(|err|
-      format!("Unable to check Archiver native extension installation status\n\n{}", err)
-    ).and_then(|installed|
-      if installed {
-        extension::uninstall(extension::NAME).map_or_else(
-          |err| Err(format!("Unable to uninstall Archiver native extension\n\n{}", err)),
-          |_| Ok("Archiver native extension uninstalled".to_string())
-        )
-      }
-      else {
-        extension::install(
-          extension::NAME,
-          extension::DESCRIPTION,
-          extension::ID
-        ).map_or_else(
-          |err| Err(format!("Unable to install Archiver native extension\n\n{}", err)),
-          |_| Ok("Archiver native extension installed".to_string())
-        )
-      }
-    );
+fn main() {
+  Native::init();
 
-    let (message, icon, exit_code) = match install_result {
-      Ok(message) => (message, winapi::um::winuser::MB_ICONINFORMATION, exitcode::OK),
-      Err(message) => (message, winapi::um::winuser::MB_ICONERROR, exitcode::IOERR)
-    };
+  let args: Vec<String> = std::env::args().skip(1).collect();
 
-    // i solemnly swear that these strings contain no zero bytes
-    let text = std::ffi::CString::new(message).unwrap();
-    let caption = std::ffi::CString::new("Archiver").unwrap();
+  let command = match cli::parse(&args) {
+    Ok(command) => command,
+    Err(message) => {
+      report(Err(format!("{}\n\n{}", message, cli::USAGE)), Native::attach_console());
 
-    unsafe {
-      user32::MessageBoxA(
-        std::ptr::null_mut(),
-        text.as_ptr(),
-        caption.as_ptr(),
-        winapi::um::winuser::MB_OK | icon
-      );
+      process::exit(exitcode::USAGE);
     }
+  };
 
-    process::exit(exit_code);
-  }
-
-  panic::set_hook(Box::new(handle_panic));
-
-  loop {
-    match read_input(io::stdin()) {
-      Ok(message) => {
-        let result = serde_json::from_value::<Message>(message).map_err(|error|
-          format!("Invalid message: {}", error)
-        ).and_then(|en| {
-          match en {
-            Message::Pick => {
-              Ok(Response::Pick {
-                msg: wfd::open_dialog(
-                  wfd::DialogParams {
-                    options: wfd::FOS_PICKFOLDERS,
-                    title: "Pick archive location",
-                    ..Default::default()
-                  }
-                ).ok().and_then(|result|
-                  result.selected_file_path.to_str().map(|path|
-                    path.to_string()
-                  ) // TODO: display an error and retry if the path is not valid UTF-8?
-                ).unwrap_or("".to_string()) // TODO: don't emit anything if the selection was cancelled
-              })
-            },
-            Message::Get { directory, hashes } => {
-              lib::hash_files(&directory).map(|names| {
-                send_message(
-                  io::stdout(),
-                  &Response::Suggestions {
-                    msg: names.values().cloned().collect::<HashSet<String>>()
-                  }
-                ).unwrap();
-
-                Response::Get {
-                  msg: hashes.iter().map(|hash|
-                    (hash.to_string(), names.get(hash).map(|name| name.to_string()))
-                  ).collect::<HashMap<String, Option<String>>>()
-                }
-              })
-            },
-            Message::Set { directory, url, hash, name, filename } => {
-              if lib::hash_files(&directory).unwrap_or_default().get(&hash).map(|found_name| found_name.to_string() == name).unwrap_or(false) {
-                return Ok(Response::Get { msg: HashMap::from([(hash, Some(name))]) })
-              }
-
-              let destination = Path::new(&directory).join(&name);
-              fs::create_dir_all(&destination).unwrap();
+  let exit_code = match command {
+    cli::Command::Host => host(),
+    cli::Command::Toggle => report(cli::toggle(), false),
+    cli::Command::Help => report(Ok(cli::USAGE.to_string()), Native::attach_console()),
+    cli::Command::Install(options) => report(cli::install(&options), Native::attach_console()),
+    cli::Command::Uninstall(options) => report(cli::uninstall(&options), Native::attach_console()),
+    cli::Command::Allow(options) => report(cli::update_allowed(&options, true), Native::attach_console()),
+    cli::Command::Disallow(options) => report(cli::update_allowed(&options, false), Native::attach_console()),
+    cli::Command::Status(options) => report_with(|out| cli::status(&options, out)),
+    cli::Command::Doctor(options) => report_with(|out| doctor::run(&options, out)),
+    cli::Command::Index(options) => {
+      // attached first, so progress can be shown while indexing
+      let console = Native::attach_console();
+      report(cli::index(&options), console)
+    },
+  };
 
-              let destination_filename = unique_filename(&destination.join(filename)).unwrap();
-              let mut downloader = Downloader::builder().download_folder(&destination).build().unwrap();
+  process::exit(exit_code);
+}
 
-              Ok(
-                downloader.download(&[
-                  Download::new(&url).file_name(&destination_filename).verify(Arc::new(move |_path, _|
-                    // File::open(path).map(|file|
-                      // if base64_hash(file) == hash {
-                        Verification::Ok
-                      // }
-                      // else {
-                        // Verification::Failed
-                      // }
-                    // ).unwrap_or(Verification::Failed)
-                  ))
-                ]).map_or_else(|err|
-                  Response::Error { error: err.to_string() },
-                  |_result| {
-                    // TODO: error handling
-                    /*
-                    result.iter().map(|item|
-                      item.as_ref().map_err(|err| err.to_string()).map(|summary| {
-                        summary.to_string()
-                      })
-                    ).collect::<Vec<_>>();
-                    */
-                  send_message(
-                    io::stdout(),
-                    &Response::Suggestions {
-                      msg: HashSet::from([name.clone()])
-                    }
-                  ).unwrap();
+fn host() -> exitcode::ExitCode {
+  panic::set_hook(Box::new(host::record_panic));
 
-                  Response::Get {
-                    msg: HashMap::from([(hash, Some(name))])
-                  }
-                })
-              )
-            }
-          }
-        });
+  let host = Host::new(cli::config_path(&cli::Options::default()), Native::picker());
 
-        match result {
-          Ok(response) => send_message(io::stdout(), &response).unwrap(),
-          Err(error) => send_message(
-            io::stdout(),
-            &Response::Error {
-              error: error
-            }
-          ).unwrap()
-        }
-      },
-      Err(Error::NoMoreInput) => {
-        break;
-      },
-      Err(error) => {
-        send_message(
-          io::stdout(),
-          &Response::Error {
-            error: format!("{}", error)
-          }
-        ).unwrap()
-      },
+  match host.run(io::stdin(), io::stdout()) {
+    Ok(()) => exitcode::OK,
+    Err(err) => {
+      eprintln!("{}", err);
+      exitcode::IOERR
     }
   }
 }
@@ -0,0 +1,62 @@
+use std::io;
+use std::path::PathBuf;
+use serde::Serialize;
+
+// the outcome of asking the user for a folder, as sent back to the extension
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag="status", rename_all="lowercase")]
+pub enum Picked {
+  Selected {
+    msg: String,
+  },
+  Cancelled,
+  // the folder can't be used since paths have to go through json, msg is a lossy version for display
+  Invalid {
+    msg: String,
+  },
+}
+
+impl From<Option<PathBuf>> for Picked {
+  fn from(path: Option<PathBuf>) -> Self {
+    match path {
+      None => Picked::Cancelled,
+      Some(path) => match path.to_str() {
+        Some(string) => Picked::Selected { msg: string.to_string() },
+        None => Picked::Invalid { msg: path.to_string_lossy().to_string() }
+      }
+    }
+  }
+}
+
+pub trait Picker: Send + Sync {
+  // Ok(None) if the user cancelled, Err if this picker can't be shown here
+  fn pick_folder(&self, title: &str) -> Result<Option<PathBuf>, io::Error>;
+}
+
+// for when there's nobody to show a dialog to
+pub struct Headless;
+
+impl Picker for Headless {
+  fn pick_folder(&self, _title: &str) -> Result<Option<PathBuf>, io::Error> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "No folder picker is available on this system"))
+  }
+}
+
+// tries each picker in turn until one of them can be shown, pickers that aren't installed don't count as errors
+pub struct Fallback(pub Vec<Box<dyn Picker>>);
+
+impl Picker for Fallback {
+  fn pick_folder(&self, title: &str) -> Result<Option<PathBuf>, io::Error> {
+    let mut last_error = None;
+
+    for picker in &self.0 {
+      match picker.pick_folder(title) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {},
+        Err(err) => last_error = Some(err),
+        picked => return picked
+      }
+    }
+
+    Err(last_error.unwrap_or_else(|| Headless.pick_folder(title).unwrap_err()))
+  }
+}
@@ -0,0 +1,127 @@
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use archive::picker::Fallback;
+use archive::picker::Headless;
+use archive::picker::Picker;
+use crate::extension::Browser;
+use crate::extension::Scope;
+use super::Platform;
+
+mod picker;
+
+pub struct Linux;
+
+fn home() -> Result<PathBuf, io::Error> {
+  std::env::var_os("HOME").filter(|home|
+    !home.is_empty()
+  ).map(PathBuf::from).ok_or_else(||
+    io::Error::new(io::ErrorKind::NotFound, "HOME is not set")
+  )
+}
+
+fn config_home() -> Result<PathBuf, io::Error> {
+  std::env::var_os("XDG_CONFIG_HOME").filter(|config_home|
+    !config_home.is_empty()
+  ).map_or_else(|| Ok(home()?.join(".config")), |config_home| Ok(PathBuf::from(config_home)))
+}
+
+// the browser's user data directory, which is where chromium-based browsers look for NativeMessagingHosts
+fn config_directory(browser: Browser) -> Result<PathBuf, io::Error> {
+  Ok(match browser {
+    Browser::Chrome => config_home()?.join("google-chrome"),
+    Browser::Chromium => config_home()?.join("chromium"),
+    Browser::Brave => config_home()?.join("BraveSoftware/Brave-Browser"),
+    Browser::Vivaldi => config_home()?.join("vivaldi"),
+    Browser::Firefox => home()?.join(".mozilla"),
+  })
+}
+
+fn system_directory(browser: Browser) -> PathBuf {
+  PathBuf::from(match browser {
+    Browser::Chrome => "/etc/opt/chrome/native-messaging-hosts",
+    Browser::Chromium => "/etc/chromium/native-messaging-hosts",
+    Browser::Brave => "/etc/opt/brave.com/brave/native-messaging-hosts",
+    Browser::Vivaldi => "/etc/opt/vivaldi/native-messaging-hosts",
+    Browser::Firefox => "/usr/lib/mozilla/native-messaging-hosts",
+  })
+}
+
+impl Platform for Linux {
+  fn detect(browser: Browser) -> bool {
+    config_directory(browser).is_ok_and(|directory| directory.is_dir())
+  }
+
+  // browsers only look in their own directories, so the location isn't configurable
+  fn manifest_path(browser: Browser, scope: Scope, name: &str, directory: Option<&Path>) -> Result<PathBuf, io::Error> {
+    if directory.is_some() {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, "The manifest location can't be changed on Linux"));
+    }
+
+    let directory = match (scope, browser) {
+      (Scope::User, Browser::Firefox) => config_directory(browser)?.join("native-messaging-hosts"),
+      (Scope::User, _) => config_directory(browser)?.join("NativeMessagingHosts"),
+      (Scope::System, _) => system_directory(browser),
+    };
+
+    Ok(directory.join(format!("{}.json", name)))
+  }
+
+  // browsers find manifests by filename, so the file itself is the registration
+  fn registered_manifest(browser: Browser, scope: Scope, name: &str) -> Result<Option<PathBuf>, io::Error> {
+    let manifest_path = Self::manifest_path(browser, scope, name, None)?;
+
+    Ok(Some(manifest_path).filter(|path| path.exists()))
+  }
+
+  fn register(_browser: Browser, _scope: Scope, _name: &str, manifest_path: &Path) -> Result<(), io::Error> {
+    if manifest_path.exists() {
+      Ok(())
+    }
+    else {
+      Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", manifest_path.display())))
+    }
+  }
+
+  fn unregister(browser: Browser, scope: Scope, name: &str) -> Result<(), io::Error> {
+    match std::fs::remove_file(Self::manifest_path(browser, scope, name, None)?) {
+      Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+      _ => Ok(())
+    }
+  }
+
+  fn config_directory() -> Result<PathBuf, io::Error> {
+    Ok(config_home()?.join("archive"))
+  }
+
+  // the desktop portal works everywhere it's running, including inside sandboxes, then whatever dialog tool the desktop is likely to have
+  fn picker() -> Box<dyn Picker> {
+    if std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none() {
+      return Box::new(Headless);
+    }
+
+    let kde = std::env::var("XDG_CURRENT_DESKTOP").is_ok_and(|desktop| desktop.to_uppercase().contains("KDE"));
+
+    let pickers: Vec<Box<dyn Picker>> = if kde {
+      vec![Box::new(picker::Portal), Box::new(picker::Kdialog), Box::new(picker::Zenity)]
+    }
+    else {
+      vec![Box::new(picker::Portal), Box::new(picker::Zenity), Box::new(picker::Kdialog)]
+    };
+
+    Box::new(Fallback(pickers))
+  }
+
+  fn message_box(caption: &str, text: &str, error: bool) {
+    if error {
+      eprintln!("{}: {}", caption, text);
+    }
+    else {
+      println!("{}: {}", caption, text);
+    }
+  }
+
+  fn attach_console() -> bool {
+    true
+  }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::ffi::OsStringExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use zbus::blocking::Connection;
+use zbus::blocking::Proxy;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::zvariant::OwnedValue;
+use zbus::zvariant::Value;
+use archive::picker::Picker;
+
+const PORTAL_DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+
+// keeps the request tokens of picks running at the same time apart, so each only hears its own response
+static NEXT_TOKEN: AtomicU64 = AtomicU64::new(0);
+
+fn portal_error(err: zbus::Error) -> io::Error {
+  io::Error::new(io::ErrorKind::Unsupported, format!("Desktop portal unavailable: {}", err))
+}
+
+// file:// uris from the portal are percent-encoded bytes, which aren't necessarily UTF-8
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+  let encoded = uri.strip_prefix("file://")?.as_bytes();
+  let mut bytes = Vec::with_capacity(encoded.len());
+  let mut i = 0;
+
+  while i < encoded.len() {
+    if encoded[i] == b'%' && i + 2 < encoded.len() {
+      bytes.push(u8::from_str_radix(std::str::from_utf8(&encoded[i + 1..i + 3]).ok()?, 16).ok()?);
+      i += 3;
+    }
+    else {
+      bytes.push(encoded[i]);
+      i += 1;
+    }
+  }
+
+  Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+// org.freedesktop.portal.FileChooser, which also works from inside flatpak and snap sandboxes
+pub struct Portal;
+
+impl Picker for Portal {
+  fn pick_folder(&self, title: &str) -> Result<Option<PathBuf>, io::Error> {
+    let connection = Connection::session().map_err(portal_error)?;
+
+    // the request path is derived from our connection name and a token of our choosing, so we can listen for
+    // the response before asking and not miss it
+    let sender = connection.unique_name().map(|name| name.trim_start_matches(':').replace('.', "_")).unwrap_or_default();
+    let token = format!("archive{}_{}", std::process::id(), NEXT_TOKEN.fetch_add(1, Ordering::Relaxed));
+    let request_path = format!("{}/request/{}/{}", PORTAL_PATH, sender, token);
+
+    let request = Proxy::new(&connection, PORTAL_DESTINATION, request_path.as_str(), "org.freedesktop.portal.Request").map_err(portal_error)?;
+    let mut responses = request.receive_signal("Response").map_err(portal_error)?;
+
+    let file_chooser = Proxy::new(&connection, PORTAL_DESTINATION, PORTAL_PATH, "org.freedesktop.portal.FileChooser").map_err(portal_error)?;
+
+    let options = HashMap::from([
+      ("handle_token", Value::from(token.as_str())),
+      ("directory", Value::from(true)),
+    ]);
+
+    let _: OwnedObjectPath = file_chooser.call("OpenFile", &("", title, options)).map_err(portal_error)?;
+
+    let response = responses.next().ok_or_else(||
+      io::Error::new(io::ErrorKind::UnexpectedEof, "Desktop portal closed the request without answering")
+    )?;
+
+    let (code, results): (u32, HashMap<String, OwnedValue>) = response.body().deserialize().map_err(portal_error)?;
+
+    match code {
+      0 => {
+        let uris: Vec<String> = results.get("uris").and_then(|uris|
+          uris.try_clone().ok()
+        ).and_then(|uris|
+          Vec::<String>::try_from(uris).ok()
+        ).unwrap_or_default();
+
+        uris.first().map(|uri|
+          uri_to_path(uri).map(Some).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Desktop portal returned a non-local folder: {}", uri)))
+        ).unwrap_or(Ok(None))
+      },
+      1 => Ok(None),
+      _ => Err(io::Error::other("Desktop portal failed to show the folder picker"))
+    }
+  }
+}
+
+// runs a dialog tool that prints the selected path, and exits unsuccessfully when cancelled
+fn run_dialog(command: &mut Command) -> Result<Option<PathBuf>, io::Error> {
+  let mut output = command.output()?;
+
+  if !output.status.success() {
+    return Ok(None);
+  }
+
+  while output.stdout.last() == Some(&b'\n') {
+    output.stdout.pop();
+  }
+
+  Ok(Some(PathBuf::from(OsString::from_vec(output.stdout))).filter(|path| !path.as_os_str().is_empty()))
+}
+
+pub struct Zenity;
+
+impl Picker for Zenity {
+  fn pick_folder(&self, title: &str) -> Result<Option<PathBuf>, io::Error> {
+    run_dialog(Command::new("zenity").args(["--file-selection", "--directory", "--title", title]))
+  }
+}
+
+pub struct Kdialog;
+
+impl Picker for Kdialog {
+  fn pick_folder(&self, title: &str) -> Result<Option<PathBuf>, io::Error> {
+    run_dialog(Command::new("kdialog").args(["--getexistingdirectory", ".", "--title", title]))
+  }
+}
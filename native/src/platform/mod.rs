@@ -0,0 +1,51 @@
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use archive::picker::Picker;
+use crate::extension::Browser;
+use crate::extension::Scope;
+
+#[cfg(windows)]
+mod windows;
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(windows)]
+pub use self::windows::Windows as Native;
+#[cfg(target_os = "linux")]
+pub use self::linux::Linux as Native;
+
+// everything the installer and the host need from the operating system
+pub trait Platform {
+  // called once at startup, before any UI is shown
+  fn init() {}
+
+  // whether `browser` appears to be installed for this user
+  fn detect(browser: Browser) -> bool;
+
+  // where the host manifest for `name` should be written for `browser`, in `directory` if given
+  fn manifest_path(browser: Browser, scope: Scope, name: &str, directory: Option<&Path>) -> Result<PathBuf, io::Error>;
+
+  // the host manifest `browser` will currently load for `name` at `scope`, if any
+  fn registered_manifest(browser: Browser, scope: Scope, name: &str) -> Result<Option<PathBuf>, io::Error>;
+
+  fn register(browser: Browser, scope: Scope, name: &str, manifest_path: &Path) -> Result<(), io::Error>;
+
+  fn unregister(browser: Browser, scope: Scope, name: &str) -> Result<(), io::Error>;
+
+  // other browsers that read the same registration as `browser`, so unregistering one unregisters them too
+  fn shares_registration(_browser: Browser) -> Vec<Browser> {
+    Vec::new()
+  }
+
+  // where the host keeps its settings for this user
+  fn config_directory() -> Result<PathBuf, io::Error>;
+
+  // the best folder picker available right now
+  fn picker() -> Box<dyn Picker>;
+
+  fn message_box(caption: &str, text: &str, error: bool);
+
+  // whether stdout and stderr reach a console the user can see, attaching to the parent's if needed
+  fn attach_console() -> bool;
+}
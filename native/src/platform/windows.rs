@@ -0,0 +1,164 @@
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use winapi::um::wincon;
+use winapi::um::winuser;
+use user32;
+use wfd;
+use winreg;
+use archive::picker::Picker;
+use crate::extension::Browser;
+use crate::extension::Scope;
+use super::Platform;
+
+pub const HOST_PATH: &str = "host.json";
+pub const FIREFOX_HOST_PATH: &str = "host-firefox.json";
+
+pub struct Windows;
+
+pub struct FolderDialog;
+
+impl Picker for FolderDialog {
+  fn pick_folder(&self, title: &str) -> Result<Option<PathBuf>, io::Error> {
+    match wfd::open_dialog(wfd::DialogParams { options: wfd::FOS_PICKFOLDERS, title, ..Default::default() }) {
+      Ok(result) => Ok(Some(result.selected_file_path)),
+      Err(wfd::DialogError::UserCancelled) => Ok(None),
+      Err(wfd::DialogError::UnsupportedFilepath) => Err(io::Error::new(io::ErrorKind::InvalidInput, "The selected folder is not on a regular filesystem")),
+      Err(wfd::DialogError::HResultFailed { error_method, hresult }) => Err(io::Error::other(format!("{} failed with HRESULT {:#x}", error_method, hresult)))
+    }
+  }
+}
+
+// vivaldi doesn't have its own key and reads chrome's instead
+fn browser_key(browser: Browser) -> &'static str {
+  match browser {
+    Browser::Chrome | Browser::Vivaldi => "Software\\Google\\Chrome",
+    Browser::Chromium => "Software\\Chromium",
+    Browser::Brave => "Software\\BraveSoftware\\Brave-Browser",
+    Browser::Firefox => "Software\\Mozilla",
+  }
+}
+
+fn root(scope: Scope) -> winreg::RegKey {
+  winreg::RegKey::predef(match scope {
+    Scope::User => winreg::enums::HKEY_CURRENT_USER,
+    Scope::System => winreg::enums::HKEY_LOCAL_MACHINE,
+  })
+}
+
+fn native_messaging_key(browser: Browser, name: &str) -> String {
+  format!("{}\\NativeMessagingHosts\\{}", browser_key(browser), name)
+}
+
+impl Platform for Windows {
+  fn init() {
+    unsafe {
+      winuser::SetProcessDpiAwarenessContext(
+        winapi::shared::windef::DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2
+      );
+    }
+  }
+
+  // vivaldi's native messaging key is chrome's, so it's found by its uninstaller or its profile instead
+  fn detect(browser: Browser) -> bool {
+    let key = match browser {
+      Browser::Firefox => "Software\\Mozilla\\Firefox",
+      Browser::Vivaldi => "Software\\Microsoft\\Windows\\CurrentVersion\\Uninstall\\Vivaldi",
+      _ => browser_key(browser),
+    };
+
+    let profile = match browser {
+      Browser::Vivaldi => std::env::var_os("LOCALAPPDATA").is_some_and(|local| Path::new(&local).join("Vivaldi").join("User Data").is_dir()),
+      _ => false,
+    };
+
+    profile || [Scope::User, Scope::System].iter().any(|scope| root(*scope).open_subkey(key).is_ok())
+  }
+
+  // chromium-based browsers all accept the same manifest, firefox needs its own
+  // the registry can point anywhere, so by default the manifest lives next to the executable
+  fn manifest_path(browser: Browser, _scope: Scope, _name: &str, directory: Option<&Path>) -> Result<PathBuf, io::Error> {
+    let filename = match browser {
+      Browser::Firefox => FIREFOX_HOST_PATH,
+      _ => HOST_PATH,
+    };
+
+    match directory {
+      Some(directory) => Ok(directory.join(filename)),
+      None => Ok(std::env::current_exe()?.parent().unwrap().join(filename))
+    }
+  }
+
+  fn registered_manifest(browser: Browser, scope: Scope, name: &str) -> Result<Option<PathBuf>, io::Error> {
+    root(scope).open_subkey(
+      native_messaging_key(browser, name)
+    ).and_then(|key|
+      key.get_value("")
+    ).map_or_else(|err|
+      match err.kind() {
+        io::ErrorKind::NotFound => Ok(None),
+        _ => Err(err)
+      },
+      |value: String| Ok(Some(PathBuf::from(value)))
+    )
+  }
+
+  fn register(browser: Browser, scope: Scope, name: &str, manifest_path: &Path) -> Result<(), io::Error> {
+    let (key, _) = root(scope).create_subkey(
+      native_messaging_key(browser, name)
+    )?;
+
+    // not really anything reasonable we can do if the path isn't valid UTF-8 since it has to go into json...
+    let host_filename = &manifest_path.to_str().unwrap().to_string();
+
+    key.set_value("", host_filename)?;
+
+    Ok(())
+  }
+
+  // tolerates the key already being gone, since browsers can share a key
+  fn unregister(browser: Browser, scope: Scope, name: &str) -> Result<(), io::Error> {
+    match root(scope).delete_subkey(native_messaging_key(browser, name)) {
+      Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+      _ => Ok(())
+    }
+  }
+
+  fn shares_registration(browser: Browser) -> Vec<Browser> {
+    Browser::ALL.iter().cloned().filter(|other| *other != browser && browser_key(*other) == browser_key(browser)).collect()
+  }
+
+  fn config_directory() -> Result<PathBuf, io::Error> {
+    std::env::var_os("APPDATA").map(|app_data|
+      PathBuf::from(app_data).join("archive")
+    ).ok_or_else(||
+      io::Error::new(io::ErrorKind::NotFound, "APPDATA is not set")
+    )
+  }
+
+  fn picker() -> Box<dyn Picker> {
+    Box::new(FolderDialog)
+  }
+
+  fn message_box(caption: &str, text: &str, error: bool) {
+    let icon = if error { winuser::MB_ICONERROR } else { winuser::MB_ICONINFORMATION };
+
+    // i solemnly swear that these strings contain no zero bytes
+    let text = std::ffi::CString::new(text).unwrap();
+    let caption = std::ffi::CString::new(caption).unwrap();
+
+    unsafe {
+      user32::MessageBoxA(
+        std::ptr::null_mut(),
+        text.as_ptr(),
+        caption.as_ptr(),
+        winuser::MB_OK | icon
+      );
+    }
+  }
+
+  // release builds use the windows subsystem, so there's no console unless we borrow the one we were started from
+  fn attach_console() -> bool {
+    cfg!(debug_assertions) || unsafe { wincon::AttachConsole(wincon::ATTACH_PARENT_PROCESS) != 0 }
+  }
+}
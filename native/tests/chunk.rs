@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use archive::chunk;
+use serde_json::json;
+
+// shaped like the host's Get response, which maps every requested hash to the folder it was found in
+fn get_response(items: &[(String, Option<String>)]) -> Vec<u8> {
+  serde_json::to_vec(&json!({
+    "id": 1,
+    "more": true,
+    "type": "get",
+    "msg": items.iter().cloned().collect::<HashMap<String, Option<String>>>()
+  })).unwrap()
+}
+
+#[test]
+fn oversized_response_is_split() {
+  let hashes: HashMap<String, Option<String>> = (0..50000).map(|number|
+    (format!("{:022}==", number), Some(format!("folder number {}", number)))
+  ).collect();
+  let items: Vec<(String, Option<String>)> = hashes.clone().into_iter().collect();
+
+  assert!(get_response(&items).len() > chunk::MAX_MESSAGE_SIZE);
+
+  let parts = chunk::split(items, &|items: &[(String, Option<String>)]| get_response(items).len() <= chunk::MAX_MESSAGE_SIZE);
+
+  assert!(parts.len() > 1);
+  assert!(parts.iter().all(|part| !part.is_empty() && get_response(part).len() <= chunk::MAX_MESSAGE_SIZE));
+  assert_eq!(parts.iter().map(|part| part.len()).sum::<usize>(), hashes.len());
+  assert_eq!(parts.into_iter().flatten().collect::<HashMap<_, _>>(), hashes);
+}
+
+#[test]
+fn small_response_is_not_split() {
+  let items = vec!["a".to_string(), "b".to_string()];
+
+  assert_eq!(chunk::split(items.clone(), &|_: &[String]| true), vec![items]);
+  assert_eq!(chunk::split(Vec::<String>::new(), &|_: &[String]| false), vec![Vec::<String>::new()]);
+}
+
+#[test]
+fn item_too_large_on_its_own_is_kept() {
+  let items = vec!["a".repeat(10), "b".to_string()];
+
+  assert_eq!(chunk::split(items, &|items: &[String]| items.iter().map(|item| item.len()).sum::<usize>() <= 5), vec![vec!["a".repeat(10)], vec!["b".to_string()]]);
+}
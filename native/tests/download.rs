@@ -0,0 +1,204 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use archive::config::Downloads;
+use archive::download;
+use archive::download::Client;
+use archive::error::Error;
+use archive::index::Index;
+use tempdir::TempDir;
+
+// answers each connection with the next of responses, keeping the requests it was sent
+struct Server {
+  url: String,
+  requests: Arc<Mutex<Vec<String>>>,
+}
+
+fn serve(responses: Vec<Vec<u8>>) -> Server {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+  let requests = Arc::new(Mutex::new(Vec::new()));
+  let received = requests.clone();
+
+  thread::spawn(move || {
+    for (mut stream, response) in listener.incoming().flatten().zip(responses) {
+      let mut request = String::new();
+
+      for line in BufReader::new(&stream).lines() {
+        match line {
+          Ok(line) if !line.is_empty() => request.push_str(&format!("{}\n", line.to_lowercase())),
+          _ => break
+        }
+      }
+
+      received.lock().unwrap().push(request);
+      stream.write_all(&response).ok();
+    }
+  });
+
+  Server { url, requests }
+}
+
+// says the body is length bytes long, even if it isn't
+fn response(status: &str, headers: &[&str], body: &str, length: usize) -> Vec<u8> {
+  let headers = headers.iter().map(|header| format!("{}\r\n", header)).collect::<String>();
+  format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n{}\r\n{}", status, length, headers, body).into_bytes()
+}
+
+fn ok(body: &str) -> Vec<u8> {
+  response("200 OK", &[], body, body.len())
+}
+
+fn client(attempts: u32) -> Client {
+  Client::new(&Downloads { attempts, backoff: 10, ..Downloads::default() })
+}
+
+fn temporary(directory: &TempDir) -> std::path::PathBuf {
+  download::temporary_file(directory.path(), "file.txt").unwrap()
+}
+
+#[test]
+fn persist_picks_a_free_name() {
+  let directory = TempDir::new("download").unwrap();
+  let requested = directory.path().join("file.txt");
+  fs::write(&requested, "taken").unwrap();
+
+  let temporary = download::temporary_file(directory.path(), "file.txt").unwrap();
+  fs::write(&temporary, "hi\n").unwrap();
+
+  let saved = download::persist(&temporary, &requested).unwrap();
+
+  assert_eq!(saved, directory.path().join("file (1).txt"));
+  assert_eq!(fs::read(&saved).unwrap(), b"hi\n");
+  assert_eq!(fs::read(&requested).unwrap(), b"taken");
+  assert!(!temporary.exists());
+}
+
+#[test]
+fn temporary_files_are_not_indexed() {
+  let directory = TempDir::new("download").unwrap();
+  fs::create_dir(directory.path().join("folder")).unwrap();
+
+  let temporary = download::temporary_file(&directory.path().join("folder"), "file.txt").unwrap();
+  fs::write(&temporary, "hi\n").unwrap();
+
+  assert!(download::is_temporary(&temporary.file_name().unwrap().to_string_lossy()));
+  assert_ne!(download::temporary_file(&directory.path().join("folder"), "file.txt").unwrap(), temporary);
+
+  let mut index = Index::load(directory.path());
+  index.refresh().unwrap();
+
+  assert!(index.names().is_empty());
+}
+
+#[test]
+fn busy_servers_are_retried() {
+  let directory = TempDir::new("download").unwrap();
+  let path = temporary(&directory);
+  let server = serve(vec![
+    response("429 Too Many Requests", &["Retry-After: 0"], "", 0),
+    response("503 Service Unavailable", &[], "", 0),
+    ok("hi\n"),
+  ]);
+
+  let attempts = client(4).download(&server.url, &path, &|| false).unwrap();
+
+  assert_eq!(attempts.iter().map(|attempt| attempt.status).collect::<Vec<_>>(), vec![Some(429), Some(503), Some(200)]);
+  assert!(attempts[0].error.is_some());
+  assert_eq!(attempts[2].error, None);
+  assert_eq!(fs::read(&path).unwrap(), b"hi\n");
+}
+
+#[test]
+fn other_errors_are_not_retried() {
+  let directory = TempDir::new("download").unwrap();
+  let server = serve(vec![response("404 Not Found", &[], "", 0), ok("hi\n")]);
+
+  match client(4).download(&server.url, &temporary(&directory), &|| false) {
+    Err(Error::Http { status: 404, attempts, .. }) => assert_eq!(attempts.len(), 1),
+    result => panic!("{:?}", result)
+  }
+}
+
+#[test]
+fn gives_up_after_every_attempt() {
+  let directory = TempDir::new("download").unwrap();
+  let server = serve(vec![response("500 Internal Server Error", &[], "", 0); 3]);
+
+  match client(2).download(&server.url, &temporary(&directory), &|| false) {
+    Err(Error::Http { status: 500, attempts, .. }) => assert_eq!(attempts.len(), 2),
+    result => panic!("{:?}", result)
+  }
+
+  assert_eq!(server.requests.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn dropped_downloads_are_resumed() {
+  let directory = TempDir::new("download").unwrap();
+  let path = temporary(&directory);
+  let server = serve(vec![
+    response("200 OK", &[], "hel", 6),
+    response("206 Partial Content", &["Content-Range: bytes 3-5/6"], "lo\n", 3),
+  ]);
+
+  let attempts = client(4).download(&server.url, &path, &|| false).unwrap();
+
+  assert_eq!(fs::read(&path).unwrap(), b"hello\n");
+  assert_eq!((attempts[0].received, attempts[1].offset, attempts[1].received), (3, 3, 3));
+  assert!(server.requests.lock().unwrap()[1].contains("range: bytes=3-"));
+}
+
+#[test]
+fn servers_that_ignore_ranges_start_over() {
+  let directory = TempDir::new("download").unwrap();
+  let path = temporary(&directory);
+  let server = serve(vec![response("200 OK", &[], "hel", 6), ok("hello\n")]);
+
+  client(4).download(&server.url, &path, &|| false).unwrap();
+
+  assert_eq!(fs::read(&path).unwrap(), b"hello\n");
+}
+
+#[test]
+fn unsatisfiable_ranges_start_over_straight_away() {
+  let directory = TempDir::new("download").unwrap();
+  let path = temporary(&directory);
+  fs::write(&path, "stale data").unwrap();
+
+  let server = serve(vec![
+    response("416 Range Not Satisfiable", &["Content-Range: bytes */6"], "", 0),
+    ok("hello\n"),
+  ]);
+
+  // starting over isn't one of the attempts, so even a client that only tries once gets there
+  let attempts = client(1).download(&server.url, &path, &|| false).unwrap();
+
+  assert_eq!(fs::read(&path).unwrap(), b"hello\n");
+  assert_eq!(attempts.len(), 2);
+  assert_eq!((attempts[1].offset, attempts[1].status), (0, Some(200)));
+  assert!(!server.requests.lock().unwrap()[1].contains("range:"));
+}
+
+#[test]
+fn unreachable_servers_are_network_errors() {
+  let directory = TempDir::new("download").unwrap();
+
+  // nothing listens on a port once its listener is gone
+  let url = format!("http://{}/file.txt", TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap());
+
+  match client(2).download(&url, &temporary(&directory), &|| false) {
+    Err(Error::Network { attempts, .. }) => assert_eq!(attempts.len(), 2),
+    result => panic!("{:?}", result)
+  }
+}
+
+#[test]
+fn cancelled_downloads_stop() {
+  let directory = TempDir::new("download").unwrap();
+  let server = serve(vec![ok("hi\n")]);
+
+  assert_eq!(client(4).download(&server.url, &temporary(&directory), &|| true), Err(Error::Cancelled));
+}
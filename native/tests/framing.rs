@@ -0,0 +1,122 @@
+use std::io;
+use std::io::{Cursor, Read, Write};
+use archive::framing;
+use archive::framing::FrameError;
+use serde_json::{json, Value};
+
+fn frame(message: &[u8]) -> Vec<u8> {
+  let mut framed = (message.len() as u32).to_ne_bytes().to_vec();
+  framed.extend_from_slice(message);
+  framed
+}
+
+// hands out one byte per read, like a pipe that's being written to slowly
+struct Trickle(Cursor<Vec<u8>>);
+
+impl Read for Trickle {
+  fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+    let end = buffer.len().min(1);
+    self.0.read(&mut buffer[..end])
+  }
+}
+
+struct Broken(io::ErrorKind);
+
+impl Write for Broken {
+  fn write(&mut self, _buffer: &[u8]) -> io::Result<usize> {
+    Err(io::Error::from(self.0))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Err(io::Error::from(self.0))
+  }
+}
+
+#[test]
+fn round_trip() {
+  let mut buffer = Vec::new();
+  framing::write_frame(&mut buffer, &json!({ "Ping": null }), 100).unwrap();
+
+  assert_eq!(buffer, frame(br#"{"Ping":null}"#));
+  assert_eq!(framing::read_frame(&mut Cursor::new(buffer), 100).unwrap(), json!({ "Ping": null }));
+}
+
+#[test]
+fn partial_reads() {
+  let mut input = frame(br#"{"Get":{"hashes":["a","b"]}}"#);
+  input.extend(frame(br#"{"Ping":null}"#));
+  let mut reader = Trickle(Cursor::new(input));
+
+  assert_eq!(framing::read_frame(&mut reader, 100).unwrap(), json!({ "Get": { "hashes": ["a", "b"] } }));
+  assert_eq!(framing::read_frame(&mut reader, 100).unwrap(), json!({ "Ping": null }));
+  assert!(matches!(framing::read_frame(&mut reader, 100), Err(FrameError::Closed)));
+}
+
+#[test]
+fn oversize_frame_is_skipped() {
+  let mut input = frame(format!("\"{}\"", "a".repeat(200)).as_bytes());
+  input.extend(frame(br#"{"Ping":null}"#));
+  let mut reader = Cursor::new(input);
+
+  let error = framing::read_frame(&mut reader, 100).unwrap_err();
+  assert!(matches!(error, FrameError::TooLarge { size: 202, limit: 100 }));
+  assert!(error.is_recoverable());
+  assert_eq!(framing::read_frame(&mut reader, 100).unwrap(), json!({ "Ping": null }));
+}
+
+#[test]
+fn invalid_utf8_is_recoverable() {
+  let mut input = frame(b"\"\xff\xfe\"");
+  input.extend(frame(br#"{"Ping":null}"#));
+  let mut reader = Cursor::new(input);
+
+  let error = framing::read_frame(&mut reader, 100).unwrap_err();
+  assert!(matches!(error, FrameError::InvalidUtf8(_)));
+  assert!(error.is_recoverable());
+  assert_eq!(framing::read_frame(&mut reader, 100).unwrap(), json!({ "Ping": null }));
+}
+
+#[test]
+fn invalid_json_is_recoverable() {
+  let mut input = frame(b"{\"Ping\"");
+  input.extend(frame(br#"{"Ping":null}"#));
+  let mut reader = Cursor::new(input);
+
+  let error = framing::read_frame(&mut reader, 100).unwrap_err();
+  assert!(matches!(error, FrameError::InvalidJson(_)));
+  assert!(error.is_recoverable());
+  assert_eq!(framing::read_frame(&mut reader, 100).unwrap(), json!({ "Ping": null }));
+}
+
+#[test]
+fn truncated_frames() {
+  assert!(matches!(framing::read_frame(&mut Cursor::new(vec![1, 0]), 100), Err(FrameError::Truncated)));
+
+  let mut input = frame(br#"{"Ping":null}"#);
+  input.truncate(input.len() - 1);
+  assert!(matches!(framing::read_frame(&mut Cursor::new(input), 100), Err(FrameError::Truncated)));
+
+  let mut input = frame(&[b' '; 200]);
+  input.truncate(100);
+  assert!(matches!(framing::read_frame(&mut Cursor::new(input), 100), Err(FrameError::Truncated)));
+}
+
+#[test]
+fn empty_input_is_closed() {
+  assert!(matches!(framing::read_frame(&mut io::empty(), 100), Err(FrameError::Closed)));
+}
+
+#[test]
+fn oversize_message_is_not_written() {
+  let mut buffer = Vec::new();
+  let error = framing::write_frame(&mut buffer, &"a".repeat(200), 100).unwrap_err();
+
+  assert!(matches!(error, FrameError::TooLarge { size: 202, limit: 100 }));
+  assert!(buffer.is_empty());
+}
+
+#[test]
+fn broken_pipe_is_closed() {
+  assert!(matches!(framing::write_frame(&mut Broken(io::ErrorKind::BrokenPipe), &Value::Null, 100), Err(FrameError::Closed)));
+  assert!(matches!(framing::write_frame(&mut Broken(io::ErrorKind::Other), &Value::Null, 100), Err(FrameError::Io(_))));
+}
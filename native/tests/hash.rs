@@ -1,4 +1,3 @@
-use archive;
 use tempdir::TempDir;
 use std::collections::HashMap;
 use std::time;
@@ -8,7 +7,6 @@ use std::io::prelude::*;
 use quickcheck::Gen;
 use quickcheck::Arbitrary;
 use quickcheck_macros::quickcheck;
-use md5::Digest;
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 struct LegalPath(String);
@@ -22,7 +20,7 @@ impl Arbitrary for LegalPath {
 
   fn shrink(&self) -> Box<dyn Iterator<Item = LegalPath>> {
     let chars: Vec<char> = self.0.chars().collect();
-    Box::new(chars.shrink().map(|x| LegalPath(x.into_iter().filter(|c| c == &'a' || c == &'b' || c == &'c' || c == &'d').collect::<String>())).filter(|c| c.0.len() > 0))
+    Box::new(chars.shrink().map(|x| LegalPath(x.into_iter().filter(|c| c == &'a' || c == &'b' || c == &'c' || c == &'d').collect::<String>())).filter(|c| !c.0.is_empty()))
   }
 }
 
@@ -53,7 +51,7 @@ fn test(
   ).collect();
 
   let applicable_changes: HashMap<String, Option<HashMap<String, Option<String>>>> = changes.iter().filter(|(subdirectory, change)|
-    !(**change == None && !cache.contains_key(*subdirectory))
+    !change.is_none() || cache.contains_key(*subdirectory)
   ).map(|(subdirectory, change)|
     match change {
       None => (subdirectory.to_string(), change.clone()),
@@ -62,7 +60,7 @@ fn test(
           subdirectory.to_string(),
           Some(
             files.iter().filter(|(file, change)|
-              !(**change == None && !cache.get(subdirectory).map(|cached| cached.contains_key(*file)).unwrap_or(false))
+              !change.is_none() || cache.get(subdirectory).map(|cached| cached.contains_key(*file)).unwrap_or(false)
             ).map(|(f, c)| (f.to_string(), c.clone().map(|_| "1B2M2Y8AsgTpgAmY7PhCfg==".to_string()))).collect()
           )
         )
@@ -70,27 +68,21 @@ fn test(
     }
   ).collect();
 
-  ["create", "copy_files", "rename_files"].iter().cloned().map(|method| {
+  ["create", "copy_files", "rename_files"].iter().cloned().all(|method| {
     let copy_temp_dir = TempDir::new("").unwrap();
     let copy_directory = copy_temp_dir.path();
 
     for (subdirectory, change) in &changes {
-      match change {
-        Some(files) => {
-          if !copy_directory.join(&subdirectory).exists() {
-            fs::create_dir(copy_directory.join(&subdirectory)).unwrap();
-          }
+      if let Some(files) = change {
+        if !copy_directory.join(subdirectory).exists() {
+          fs::create_dir(copy_directory.join(subdirectory)).unwrap();
+        }
 
-          for (file, change) in files {
-            match change {
-              Some(_content) => {
-                fs::File::create(copy_directory.join(&subdirectory).join(file)).unwrap();
-              },
-              _ => {}
-            }
+        for (file, change) in files {
+          if let Some(_content) = change {
+            fs::File::create(copy_directory.join(subdirectory).join(file)).unwrap();
           }
-        },
-        _ => {}
+        }
       };
     }
 
@@ -98,10 +90,10 @@ fn test(
     let directory = temp_dir.path();
 
     for (subdirectory, files) in &cache {
-      fs::create_dir(directory.join(&subdirectory)).unwrap();
+      fs::create_dir(directory.join(subdirectory)).unwrap();
 
       for (file, content) in files {
-        fs::File::create(directory.join(&subdirectory).join(file)).unwrap().write_all(content.as_bytes()).unwrap();
+        fs::File::create(directory.join(subdirectory).join(file)).unwrap().write_all(content.as_bytes()).unwrap();
       }
     }
 
@@ -112,13 +104,13 @@ fn test(
     for (subdirectory, change) in &changes {
       match change {
         Some(files) => {
-          if !directory.join(&subdirectory).exists() {
+          if !directory.join(subdirectory).exists() {
             match method {
               "rename_directory" => {
-                fs::rename(copy_directory.join(&subdirectory), directory.join(&subdirectory)).unwrap();
+                fs::rename(copy_directory.join(subdirectory), directory.join(subdirectory)).unwrap();
               },
               _ => {
-                fs::create_dir(directory.join(&subdirectory)).unwrap();
+                fs::create_dir(directory.join(subdirectory)).unwrap();
               }
             }
           }
@@ -128,35 +120,35 @@ fn test(
               Some(_content) => {
                 match method {
                   "create" => {
-                    fs::File::create(directory.join(&subdirectory).join(file)).unwrap();
+                    fs::File::create(directory.join(subdirectory).join(file)).unwrap();
                   },
                   "copy_files" => {
-                    fs::copy(copy_directory.join(&subdirectory).join(file), directory.join(&subdirectory).join(file)).unwrap();
+                    fs::copy(copy_directory.join(subdirectory).join(file), directory.join(subdirectory).join(file)).unwrap();
                   },
                   "rename_files" => {
-                    fs::rename(copy_directory.join(&subdirectory).join(file), directory.join(&subdirectory).join(file)).unwrap();
+                    fs::rename(copy_directory.join(subdirectory).join(file), directory.join(subdirectory).join(file)).unwrap();
                   },
                   _ => {}
                 }
               },
               None => {
-                if directory.join(&subdirectory).join(file).exists() {
-                  fs::remove_file(directory.join(&subdirectory).join(file)).unwrap();
+                if directory.join(subdirectory).join(file).exists() {
+                  fs::remove_file(directory.join(subdirectory).join(file)).unwrap();
                 }
               }
             }
           }
         },
         None => {
-          if directory.join(&subdirectory).exists() {
-            fs::remove_dir(directory.join(&subdirectory)).unwrap();
+          if directory.join(subdirectory).exists() {
+            fs::remove_dir(directory.join(subdirectory)).unwrap();
           }
         }
       }
     }
 
-    let result = archive::update_cache(&cache, &Some(as_of), &directory);
+    let result = archive::update_cache(&cache, &Some(as_of), directory);
     println!("Expected {:?} to equal {:?}", result, applicable_changes);
     result == applicable_changes
-  }).all(|result| result)
+  })
 }
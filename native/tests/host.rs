@@ -0,0 +1,764 @@
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use archive::framing;
+use archive::framing::FrameError;
+use archive::host::Host;
+use archive::jobs::{Item, Journal, State};
+use archive::picker::{Headless, Picker};
+use serde_json::{json, Value};
+use tempdir::TempDir;
+
+struct Picks(Option<PathBuf>);
+
+impl Picker for Picks {
+  fn pick_folder(&self, _title: &str) -> Result<Option<PathBuf>, io::Error> {
+    Ok(self.0.clone())
+  }
+}
+
+struct Panics;
+
+impl Picker for Panics {
+  fn pick_folder(&self, _title: &str) -> Result<Option<PathBuf>, io::Error> {
+    panic!("no picker here")
+  }
+}
+
+fn host(directory: &TempDir, picker: impl Picker + 'static) -> Host {
+  Host::new(Ok(directory.path().join("config.json")), Box::new(picker))
+}
+
+fn input(messages: &[Value]) -> Cursor<Vec<u8>> {
+  let mut input = Vec::new();
+
+  for message in messages {
+    framing::write_frame(&mut input, message, framing::MAX_INCOMING_SIZE).unwrap();
+  }
+
+  Cursor::new(input)
+}
+
+fn responses(output: Vec<u8>) -> Vec<Value> {
+  let mut output = Cursor::new(output);
+  let mut responses = Vec::new();
+
+  loop {
+    match framing::read_frame(&mut output, framing::MAX_INCOMING_SIZE) {
+      Ok(response) => responses.push(response),
+      Err(FrameError::Closed) => return responses,
+      Err(err) => panic!("{}", err)
+    }
+  }
+}
+
+fn exchange(host: &Host, messages: &[Value]) -> Vec<Value> {
+  let mut output = Vec::new();
+  host.run(input(messages), &mut output).unwrap();
+  responses(output)
+}
+
+// messages are handled concurrently, so responses to different messages can arrive in any order
+fn with_id(responses: &[Value], id: Value) -> Vec<Value> {
+  responses.iter().filter(|response| response.get("id") == Some(&id)).cloned().collect()
+}
+
+// answers every request with body, and returns the url to request
+fn serve(body: &'static [u8]) -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+
+  thread::spawn(move || {
+    for mut stream in listener.incoming().flatten() {
+      // the request itself doesn't matter, but has to be read before answering
+      for line in BufReader::new(&stream).lines() {
+        if line.map_or(true, |line| line.is_empty()) {
+          break
+        }
+      }
+
+      write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).ok();
+      stream.write_all(body).ok();
+    }
+  });
+
+  url
+}
+
+// sends a byte at a time for as long as the client keeps reading, after letting `started` know it's been asked
+fn trickle(started: mpsc::Sender<()>) -> String {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+
+  thread::spawn(move || {
+    for mut stream in listener.incoming().flatten() {
+      for line in BufReader::new(&stream).lines() {
+        if line.map_or(true, |line| line.is_empty()) {
+          break
+        }
+      }
+
+      started.send(()).ok();
+      write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: 1000000\r\nConnection: close\r\n\r\n").ok();
+
+      while stream.write_all(b"x").and_then(|_| stream.flush()).is_ok() {
+        thread::sleep(Duration::from_millis(10));
+      }
+    }
+  });
+
+  url
+}
+
+// answers every request with body after a moment, letting `started` know it's been asked, and returns the url to request and how many requests it's had
+fn serve_slowly(body: &'static [u8], started: mpsc::Sender<()>) -> (String, Arc<AtomicUsize>) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let url = format!("http://{}/file.txt", listener.local_addr().unwrap());
+  let requests = Arc::new(AtomicUsize::new(0));
+  let counted = requests.clone();
+
+  thread::spawn(move || {
+    for mut stream in listener.incoming().flatten() {
+      for line in BufReader::new(&stream).lines() {
+        if line.map_or(true, |line| line.is_empty()) {
+          break
+        }
+      }
+
+      counted.fetch_add(1, Ordering::Relaxed);
+      started.send(()).ok();
+      thread::sleep(Duration::from_millis(200));
+
+      write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).ok();
+      stream.write_all(body).ok();
+    }
+  });
+
+  (url, requests)
+}
+
+// runs the host over a pipe, sending `later` once `ready` says so, so it arrives while the messages before it are being handled
+fn exchange_later(host: &Host, messages: &[Value], ready: mpsc::Receiver<()>, later: &[Value]) -> Vec<Value> {
+  let (reader, mut writer) = io::pipe().unwrap();
+  let mut output = Vec::new();
+  let (messages, later) = (messages.to_vec(), later.to_vec());
+
+  let sender = thread::spawn(move || {
+    for message in messages {
+      framing::write_frame(&mut writer, &message, framing::MAX_INCOMING_SIZE).unwrap();
+    }
+
+    ready.recv_timeout(Duration::from_secs(5)).unwrap();
+
+    for message in later {
+      framing::write_frame(&mut writer, &message, framing::MAX_INCOMING_SIZE).unwrap();
+    }
+  });
+
+  host.run(reader, &mut output).unwrap();
+  sender.join().unwrap();
+
+  responses(output)
+}
+
+// progress is reported while rescanning, with timings that can't be compared exactly
+fn without_progress(responses: Vec<Value>) -> Vec<Value> {
+  responses.into_iter().filter(|response| response["type"] != "progress").collect()
+}
+
+#[test]
+fn ping_with_id() {
+  let directory = TempDir::new("host").unwrap();
+
+  let responses = exchange(&host(&directory, Headless), &[json!({ "Ping": null, "id": 3 }), json!({ "Ping": null })]);
+
+  assert_eq!(responses.len(), 2);
+  assert!(responses.contains(&json!({ "id": 3, "type": "pong" })));
+  assert!(responses.contains(&json!({ "type": "pong" })));
+}
+
+#[test]
+fn hello() {
+  let directory = TempDir::new("host").unwrap();
+  let responses = exchange(&host(&directory, Headless), &[json!({ "Hello": { "version": 2 } })]);
+
+  assert_eq!(responses[0]["type"], "hello");
+  assert_eq!(responses[0]["version"], archive::host::PROTOCOL_VERSION);
+  assert_eq!(responses[0]["capabilities"]["hashes"], json!(["md5"]));
+  assert_eq!(responses[0]["capabilities"]["progress"], true);
+  assert_eq!(responses[0]["capabilities"]["jobs"], true);
+}
+
+#[test]
+fn invalid_messages_are_answered() {
+  let directory = TempDir::new("host").unwrap();
+  let mut messages = input(&[json!({ "Nope": null, "id": "a" })]).into_inner();
+  messages.extend(4u32.to_ne_bytes());
+  messages.extend(b"\"\xff\xfe\"");
+  messages.extend(input(&[json!({ "Ping": null, "id": "b" })]).into_inner());
+
+  let mut output = Vec::new();
+  host(&directory, Headless).run(Cursor::new(messages), &mut output).unwrap();
+  let responses = responses(output);
+
+  assert_eq!(responses.len(), 3);
+  assert_eq!(with_id(&responses, json!("a"))[0]["code"], "invalid_message");
+  assert_eq!(responses.iter().find(|response| response.get("id").is_none()).unwrap()["code"], "invalid_message");
+  assert_eq!(with_id(&responses, json!("b")), vec![json!({ "id": "b", "type": "pong" })]);
+}
+
+#[test]
+fn truncated_input_fails() {
+  let directory = TempDir::new("host").unwrap();
+  let mut messages = input(&[json!({ "Ping": null })]).into_inner();
+  messages.extend([10, 0, 0, 0, b'{']);
+
+  let mut output = Vec::new();
+  let result = host(&directory, Headless).run(Cursor::new(messages), &mut output);
+
+  assert!(matches!(result, Err(FrameError::Truncated)));
+  assert_eq!(responses(output), vec![json!({ "type": "pong" })]);
+}
+
+struct Closed;
+
+impl Write for Closed {
+  fn write(&mut self, _buffer: &[u8]) -> io::Result<usize> {
+    Err(io::Error::from(io::ErrorKind::BrokenPipe))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+#[test]
+fn broken_pipe_stops_the_host() {
+  let directory = TempDir::new("host").unwrap();
+
+  assert!(host(&directory, Headless).run(input(&[json!({ "Ping": null }), json!({ "Ping": null })]), Closed).is_ok());
+}
+
+#[test]
+fn pick() {
+  let directory = TempDir::new("host").unwrap();
+
+  assert_eq!(
+    exchange(&host(&directory, Picks(Some(PathBuf::from("/archive")))), &[json!({ "Pick": null })]),
+    vec![json!({ "type": "pick", "status": "selected", "msg": "/archive" })]
+  );
+  assert_eq!(
+    exchange(&host(&directory, Picks(None)), &[json!({ "Pick": null })]),
+    vec![json!({ "type": "pick", "status": "cancelled" })]
+  );
+  assert_eq!(exchange(&host(&directory, Headless), &[json!({ "Pick": null })])[0]["code"], "unavailable");
+}
+
+#[test]
+fn panics_are_answered() {
+  let directory = TempDir::new("host").unwrap();
+  let responses = exchange(&host(&directory, Panics), &[json!({ "Pick": null, "id": 1 }), json!({ "Ping": null, "id": 2 })]);
+  let panicked = with_id(&responses, json!(1));
+
+  assert_eq!(panicked[0]["code"], "panic");
+  assert_eq!(panicked[0]["error"], "Panic: no picker here");
+  assert_eq!(with_id(&responses, json!(2)), vec![json!({ "id": 2, "type": "pong" })]);
+}
+
+#[test]
+fn config() {
+  let directory = TempDir::new("host").unwrap();
+  let host = host(&directory, Headless);
+
+  let responses = exchange(&host, &[json!({ "GetConfig": null })]);
+
+  assert_eq!(responses[0]["msg"]["roots"], json!([]));
+  assert_eq!(responses[0]["msg"]["filename"], "original");
+
+  let responses = exchange(&host, &[json!({ "SetConfig": { "roots": ["/archive"], "filename": "server" } })]);
+
+  assert_eq!(responses[0]["msg"]["roots"], json!(["/archive"]));
+  assert_eq!(exchange(&host, &[json!({ "GetConfig": null })])[0]["msg"]["filename"], "server");
+}
+
+#[test]
+fn get_without_directory() {
+  let directory = TempDir::new("host").unwrap();
+  let responses = exchange(&host(&directory, Headless), &[json!({ "Get": { "hashes": [] } })]);
+
+  assert_eq!(responses[0]["code"], "directory_missing");
+}
+
+#[test]
+fn get() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(archive.join("folder")).unwrap();
+  fs::write(archive.join("folder").join("file.txt"), "hi\n").unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  // nothing is known before the first scan, so the answer comes as a follow up
+  assert_eq!(
+    without_progress(exchange(&host, &[json!({ "Get": { "hashes": ["dk76iD3aHhHbR2ccSju9ng==", "missing"] }, "id": 2 })])),
+    vec![
+      json!({ "id": 2, "more": true, "type": "suggestions", "msg": [] }),
+      json!({ "id": 2, "more": true, "type": "get", "msg": { "dk76iD3aHhHbR2ccSju9ng==": null, "missing": null } }),
+      json!({ "id": 2, "more": true, "type": "suggestions", "msg": ["folder"] }),
+      json!({ "id": 2, "type": "get", "msg": { "dk76iD3aHhHbR2ccSju9ng==": "folder" } }),
+    ]
+  );
+
+  // after which it's answered straight away
+  assert_eq!(
+    exchange(&host, &[json!({ "Get": { "hashes": ["dk76iD3aHhHbR2ccSju9ng==", "missing"] }, "id": 3 })]),
+    vec![
+      json!({ "id": 3, "more": true, "type": "suggestions", "msg": ["folder"] }),
+      json!({ "id": 3, "type": "get", "msg": { "dk76iD3aHhHbR2ccSju9ng==": "folder", "missing": null } }),
+    ]
+  );
+}
+
+#[test]
+fn oversized_get_is_split_into_frames_that_fit() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(archive.join("folder")).unwrap();
+  fs::write(archive.join("folder").join("file.txt"), "hi\n").unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } }), json!({ "Get": { "hashes": [] } })]);
+
+  let mut hashes = (0..50000).map(|number| format!("{:022}==", number)).collect::<Vec<_>>();
+  hashes.push("dk76iD3aHhHbR2ccSju9ng==".to_string());
+
+  let mut output = Vec::new();
+  host.run(input(&[json!({ "Get": { "hashes": hashes }, "id": 2 })]), &mut output).unwrap();
+
+  // the length prefix of every frame, read back without going through read_frame so their sizes can be checked
+  let mut sizes = Vec::new();
+  let mut rest = &output[..];
+
+  while !rest.is_empty() {
+    let size = u32::from_ne_bytes(rest[..4].try_into().unwrap()) as usize;
+    sizes.push(size);
+    rest = &rest[4 + size..];
+  }
+
+  let responses = responses(output);
+  let gets = responses.iter().filter(|response| response["type"] == "get").collect::<Vec<_>>();
+
+  assert!(gets.len() > 1);
+  assert!(sizes.iter().all(|size| *size <= archive::chunk::MAX_MESSAGE_SIZE));
+  assert!(responses[..responses.len() - 1].iter().all(|response| response["more"] == true));
+  assert_eq!(responses.last().unwrap().get("more"), None);
+
+  let mut merged = serde_json::Map::new();
+
+  for get in gets {
+    merged.extend(get["msg"].as_object().unwrap().clone());
+  }
+
+  let mut expected = hashes.iter().map(|hash| (hash.to_string(), Value::Null)).collect::<serde_json::Map<_, _>>();
+  expected.insert("dk76iD3aHhHbR2ccSju9ng==".to_string(), json!("folder"));
+
+  assert_eq!(merged, expected);
+}
+
+#[test]
+fn get_answers_from_the_saved_cache_first() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(archive.join("folder")).unwrap();
+  fs::write(archive.join("folder").join("file.txt"), "hi\n").unwrap();
+  exchange(&host(&directory, Headless), &[json!({ "SetConfig": { "roots": [archive] } })]);
+  exchange(&host(&directory, Headless), &[json!({ "Get": { "hashes": [] } })]);
+
+  // modification times are only as precise as the filesystem's clock
+  thread::sleep(Duration::from_millis(50));
+  fs::write(archive.join("folder").join("other.txt"), "other\n").unwrap();
+
+  // a new host starts from the cache the last one saved, and rescans after answering from it
+  assert_eq!(
+    without_progress(exchange(&host(&directory, Headless), &[json!({ "Get": { "hashes": ["dk76iD3aHhHbR2ccSju9ng==", "uneQsXCLccsrYbGjDYJHEg=="] } })])),
+    vec![
+      json!({ "more": true, "type": "suggestions", "msg": ["folder"] }),
+      json!({ "more": true, "type": "get", "msg": { "dk76iD3aHhHbR2ccSju9ng==": "folder", "uneQsXCLccsrYbGjDYJHEg==": null } }),
+      json!({ "type": "get", "msg": { "uneQsXCLccsrYbGjDYJHEg==": "folder" } }),
+    ]
+  );
+}
+
+#[test]
+fn get_reports_progress_while_rescanning() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(archive.join("folder")).unwrap();
+  fs::write(archive.join("folder").join("file.txt"), "hi\n").unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  let responses = exchange(&host, &[json!({ "Get": { "hashes": [] }, "id": 2 })]);
+  let progress = responses.iter().filter(|response| response["type"] == "progress").collect::<Vec<_>>();
+
+  assert!(progress.iter().all(|progress| progress["id"] == 2 && progress["more"] == true));
+  assert_eq!(progress.first().unwrap()["files_hashed"], 0);
+  assert_eq!(progress.last().unwrap()["subfolder"], "folder");
+  assert_eq!(progress.last().unwrap()["files_discovered"], 1);
+  assert_eq!(progress.last().unwrap()["files_hashed"], 1);
+  assert_eq!(progress.last().unwrap()["bytes_hashed"], 3);
+  assert_eq!(responses.last().unwrap()["type"], "get");
+
+  // nothing new to hash the next time, so nothing to report
+  assert!(exchange(&host, &[json!({ "Get": { "hashes": [] } })]).iter().all(|response| response["type"] != "progress"));
+}
+
+#[test]
+fn set_does_not_wait_for_a_rescan() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(archive.join("large")).unwrap();
+  fs::write(archive.join("large").join("large.bin"), vec![0; 64 * 1024 * 1024]).unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  let (ready, wait) = mpsc::channel();
+  thread::spawn(move || {
+    thread::sleep(Duration::from_millis(100));
+    ready.send(()).ok();
+  });
+
+  let responses = exchange_later(&host,
+    &[json!({ "Get": { "hashes": [] }, "id": 1 })],
+    wait,
+    &[json!({ "Set": { "url": serve(b"hi\n"), "hash": "dk76iD3aHhHbR2ccSju9ng==", "name": "folder", "filename": "file.txt" }, "id": 2 })]
+  );
+
+  // answered before the Get that was already rescanning is done
+  let last = |id| responses.iter().rposition(|response| response["id"] == id && response.get("more").is_none()).unwrap();
+
+  assert!(last(2) < last(1), "{:?}", responses);
+  assert_eq!(responses[last(2)]["msg"]["dk76iD3aHhHbR2ccSju9ng=="], "folder");
+  assert_eq!(fs::read(archive.join("folder").join("file.txt")).unwrap(), b"hi\n");
+}
+
+#[test]
+fn cancel_without_anything_running() {
+  let directory = TempDir::new("host").unwrap();
+
+  assert_eq!(
+    exchange(&host(&directory, Headless), &[json!({ "Cancel": { "id": 5 }, "id": 6 })]),
+    vec![json!({ "id": 6, "type": "cancel", "msg": false })]
+  );
+}
+
+#[test]
+fn cancel_stops_a_running_download() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(&archive).unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  let (started, ready) = mpsc::channel();
+  let responses = exchange_later(&host,
+    &[json!({ "Set": { "url": trickle(started), "hash": "hash", "name": "folder", "filename": "file.txt" }, "id": 1 })],
+    ready,
+    &[json!({ "Cancel": { "id": 1 }, "id": 2 })]
+  );
+
+  assert_eq!(with_id(&responses, json!(2)), vec![json!({ "id": 2, "type": "cancel", "msg": true })]);
+  assert_eq!(with_id(&responses, json!(1)).last().unwrap()["code"], "cancelled");
+
+  // neither the file nor what had been downloaded of it is left behind, and it's not waiting to be resumed
+  assert_eq!(fs::read_dir(archive.join("folder")).unwrap().count(), 0);
+  assert!(Journal::load(&archive).all().is_empty());
+}
+
+#[test]
+fn duplicate_sets_are_downloaded_once() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(&archive).unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  let (started, ready) = mpsc::channel();
+  let (url, requests) = serve_slowly(b"hi\n", started);
+  let set = |id| json!({ "Set": { "url": url, "hash": "dk76iD3aHhHbR2ccSju9ng==", "name": "folder", "filename": "file.txt" }, "id": id });
+  let responses = exchange_later(&host, &[set(1)], ready, &[set(2)]);
+
+  // the second waits for the first rather than failing or saving another copy
+  for id in [1, 2] {
+    assert_eq!(with_id(&responses, json!(id)).last().unwrap()["msg"]["dk76iD3aHhHbR2ccSju9ng=="], "folder");
+  }
+
+  assert_eq!(fs::read_dir(archive.join("folder")).unwrap().count(), 1);
+  assert_eq!(fs::read(archive.join("folder").join("file.txt")).unwrap(), b"hi\n");
+  assert_eq!(requests.load(Ordering::Relaxed), 1);
+  assert!(Journal::load(&archive).all().is_empty());
+}
+
+#[test]
+fn set_rejects_paths_outside_the_archive() {
+  let directory = TempDir::new("host").unwrap();
+  let responses = exchange(&host(&directory, Headless), &[
+    json!({ "Set": { "directory": directory.path(), "url": "http://127.0.0.1:1/", "hash": "", "name": "..", "filename": "file.txt" } }),
+    json!({ "Set": { "directory": directory.path(), "url": "http://127.0.0.1:1/", "hash": "", "name": "folder", "filename": "../file.txt" } }),
+  ]);
+
+  assert_eq!(responses.len(), 2);
+  assert!(responses.iter().all(|response| response["code"] == "path_rejected"));
+  assert!(!directory.path().join("file.txt").exists());
+}
+
+#[test]
+fn set_many_reports_each_item() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(archive.join("folder")).unwrap();
+  fs::write(archive.join("folder").join("file.txt"), "hi\n").unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive], "downloads": { "attempts": 1 } } })]);
+
+  // Set goes by what's already been indexed, which a Get brings up to date
+  exchange(&host, &[json!({ "Get": { "hashes": [] } })]);
+
+  let responses = exchange(&host, &[json!({ "SetMany": { "items": [
+    { "url": "http://127.0.0.1:1/file.txt", "hash": "dk76iD3aHhHbR2ccSju9ng==", "name": "folder", "filename": "file.txt" },
+    { "url": "http://127.0.0.1:1/other.txt", "hash": "other", "name": "..", "filename": "other.txt" },
+    { "url": "http://127.0.0.1:1/missing.txt", "hash": "missing", "name": "folder", "filename": "missing.txt" },
+  ] }, "id": 1 })]);
+  let last = responses.last().unwrap();
+
+  assert_eq!(last["type"], "set_many");
+  assert_eq!(last["msg"][0], json!({ "hash": "dk76iD3aHhHbR2ccSju9ng==", "name": "folder" }));
+  assert_eq!(last["msg"][1]["hash"], "other");
+  assert_eq!(last["msg"][1]["error"]["code"], "path_rejected");
+  assert_eq!(last["msg"][2]["hash"], "missing");
+  assert_eq!(last["msg"][2]["error"]["code"], "network");
+  assert!(!archive.join("folder").join("missing.txt").exists());
+
+  // and what was archived, the same way Set does
+  assert!(responses.contains(&json!({ "id": 1, "more": true, "type": "get", "msg": { "dk76iD3aHhHbR2ccSju9ng==": "folder" } })));
+}
+
+#[test]
+fn set_verifies_the_hash() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(&archive).unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  let responses = exchange(&host, &[json!({ "Set": { "url": serve(b"not hi\n"), "hash": "dk76iD3aHhHbR2ccSju9ng==", "name": "folder", "filename": "file.txt" } })]);
+  let error = responses.last().unwrap();
+
+  assert_eq!(error["code"], "hash_mismatch");
+  assert_eq!(error["details"]["expected"], "dk76iD3aHhHbR2ccSju9ng==");
+  assert!(!archive.join("folder").join("file.txt").exists());
+
+  // kept in the journal to be retried
+  let jobs = Journal::load(&archive).all();
+  assert_eq!(jobs.len(), 1);
+  assert_eq!(jobs[0].item.hash, "dk76iD3aHhHbR2ccSju9ng==");
+  assert!(matches!(&jobs[0].state, State::Failed { code, .. } if code == "hash_mismatch"));
+
+  // a later download that matches is archived, and clears the mismatch
+  let responses = exchange(&host, &[json!({ "Set": { "url": serve(b"hi\n"), "hash": "dk76iD3aHhHbR2ccSju9ng==", "name": "folder", "filename": "file.txt" } })]);
+
+  assert_eq!(responses.last().unwrap(), &json!({ "type": "get", "msg": { "dk76iD3aHhHbR2ccSju9ng==": "folder" } }));
+  assert_eq!(fs::read(archive.join("folder").join("file.txt")).unwrap(), b"hi\n");
+  assert!(Journal::load(&archive).all().is_empty());
+}
+
+// only picks a folder once `count` pickers are open at the same time
+struct Together {
+  count: usize,
+  open: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl Picker for Together {
+  fn pick_folder(&self, _title: &str) -> Result<Option<PathBuf>, io::Error> {
+    let (open, changed) = &*self.open;
+    let mut open = open.lock().unwrap();
+    *open += 1;
+    changed.notify_all();
+
+    let (open, timeout) = changed.wait_timeout_while(open, Duration::from_secs(5), |open| *open < self.count).unwrap();
+
+    if timeout.timed_out() {
+      Err(io::Error::new(io::ErrorKind::TimedOut, format!("only {} pickers were open at once", open)))
+    }
+    else {
+      Ok(Some(PathBuf::from("/archive")))
+    }
+  }
+}
+
+#[test]
+fn messages_are_handled_concurrently() {
+  let directory = TempDir::new("host").unwrap();
+  let host = host(&directory, Together { count: 2, open: Arc::new((Mutex::new(0), Condvar::new())) });
+
+  let responses = exchange(&host, &[json!({ "Pick": null, "id": 1 }), json!({ "Pick": null, "id": 2 })]);
+
+  assert_eq!(responses.len(), 2);
+  assert!(responses.iter().all(|response| response["status"] == "selected"), "{:?}", responses);
+}
+
+#[test]
+fn failed_downloads_can_be_retried() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(&archive).unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive], "limits": { "max_file_size": 1 } } })]);
+
+  let responses = exchange(&host, &[json!({ "Set": { "url": serve(b"hi\n"), "hash": "dk76iD3aHhHbR2ccSju9ng==", "name": "folder", "filename": "file.txt" } })]);
+  assert_eq!(responses.last().unwrap()["code"], "too_large");
+
+  let responses = exchange(&host, &[json!({ "ListJobs": {} })]);
+  let jobs = &responses[0]["msg"][archive.to_str().unwrap()];
+
+  assert_eq!(jobs.as_array().unwrap().len(), 1);
+  assert_eq!(jobs[0]["state"], "failed");
+  assert_eq!(jobs[0]["code"], "too_large");
+  assert_eq!(jobs[0]["filename"], "file.txt");
+
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+  let responses = exchange(&host, &[json!({ "RetryJob": { "id": jobs[0]["id"] } })]);
+  let job = responses.last().unwrap();
+
+  assert_eq!(job["type"], "job");
+  assert_eq!(job["directory"], archive.to_str().unwrap());
+  assert_eq!(job["msg"]["state"], "done");
+  assert_eq!(job["msg"]["name"], "folder");
+  assert_eq!(fs::read(archive.join("folder").join("file.txt")).unwrap(), b"hi\n");
+  assert!(Journal::load(&archive).all().is_empty());
+}
+
+#[test]
+fn jobs_can_be_dropped() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(&archive).unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive], "downloads": { "attempts": 1 } } })]);
+  exchange(&host, &[json!({ "Set": { "url": "http://127.0.0.1:1/file.txt", "hash": "hash", "name": "folder", "filename": "file.txt" } })]);
+
+  let id = Journal::load(&archive).all()[0].id;
+  let responses = exchange(&host, &[json!({ "DropJob": { "id": id } }), json!({ "DropJob": { "id": id + 1 }, "id": 2 })]);
+
+  assert!(responses.iter().any(|response| response["type"] == "job" && response["msg"]["state"] == "dropped" && response["msg"]["id"] == id));
+  assert_eq!(with_id(&responses, json!(2))[0]["code"], "invalid_message");
+  assert!(Journal::load(&archive).all().is_empty());
+}
+
+#[test]
+fn running_jobs_can_be_dropped() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(&archive).unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  let (started, ready) = mpsc::channel();
+  let responses = exchange_later(&host,
+    &[json!({ "Set": { "url": trickle(started), "hash": "hash", "name": "folder", "filename": "file.txt" }, "id": 1 })],
+    ready,
+    &[json!({ "DropJob": { "id": 0 }, "id": 2 })]
+  );
+
+  assert_eq!(with_id(&responses, json!(2))[0]["msg"]["state"], "dropped");
+  assert_eq!(with_id(&responses, json!(1)).last().unwrap()["code"], "cancelled");
+
+  // what had been downloaded is removed by the download once it notices
+  assert_eq!(fs::read_dir(archive.join("folder")).unwrap().count(), 0);
+  assert!(Journal::load(&archive).all().is_empty());
+  assert!(!archive.join(".jobs.0.lock").exists());
+}
+
+#[test]
+fn archived_jobs_are_finished_without_downloading() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(archive.join("folder")).unwrap();
+  fs::write(archive.join("folder").join("file.txt"), b"hi\n").unwrap();
+
+  let host = host(&directory, Headless);
+  exchange(&host, &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  // saved some other way after it was queued, from somewhere that can't be downloaded from any more
+  let id = Journal::load(&archive).add(Item {
+    url: "http://127.0.0.1:1/file.txt".to_string(),
+    hash: "dk76iD3aHhHbR2ccSju9ng==".to_string(),
+    name: "folder".to_string(),
+    filename: "file.txt".to_string(),
+    original_filename: None,
+  }).unwrap();
+
+  let responses = exchange(&host, &[json!({ "RetryJob": { "id": id } })]);
+
+  assert_eq!(responses.last().unwrap()["msg"]["state"], "done");
+  assert_eq!(fs::read_dir(archive.join("folder")).unwrap().count(), 1);
+  assert!(Journal::load(&archive).all().is_empty());
+}
+
+#[test]
+fn unfinished_jobs_are_resumed_once_asked() {
+  let directory = TempDir::new("host").unwrap();
+  let archive = directory.path().join("archive");
+  fs::create_dir_all(&archive).unwrap();
+
+  exchange(&host(&directory, Headless), &[json!({ "SetConfig": { "roots": [archive] } })]);
+
+  // as hosts that were stopped before they got to the downloads would have left them
+  let item = |url: &str, name: &str| Item {
+    url: url.to_string(),
+    hash: "dk76iD3aHhHbR2ccSju9ng==".to_string(),
+    name: name.to_string(),
+    filename: "file.txt".to_string(),
+    original_filename: None,
+  };
+
+  let (started, ready) = mpsc::channel();
+  let (url, _) = serve_slowly(b"hi\n", started);
+  let journal = Journal::load(&archive);
+  let held = journal.add(item(&url, "held")).unwrap();
+  journal.add(item(&url, "folder")).unwrap();
+
+  // hosts that weren't asked to, such as the ones started for a single message, leave them alone
+  let responses = exchange(&host(&directory, Headless), &[json!({ "Hello": { "version": 2 }, "id": "hello" })]);
+
+  assert_eq!(responses.len(), 1);
+  assert_eq!(journal.unfinished().len(), 2);
+
+  // as if another host were still working on it
+  let claim = journal.claim(held).unwrap().unwrap();
+  let responses = exchange_later(&host(&directory, Headless), &[json!({ "Hello": { "version": 2, "resume_jobs": true }, "id": "hello" })], ready, &[]);
+
+  // only the handshake is answered, and a download that's been started is finished after the browser goes
+  assert_eq!(responses.len(), 1);
+  assert_eq!(fs::read(archive.join("folder").join("file.txt")).unwrap(), b"hi\n");
+  assert!(!archive.join("held").exists());
+  assert_eq!(journal.unfinished(), vec![held]);
+
+  drop(claim);
+}
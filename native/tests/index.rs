@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::Duration;
+use archive::index::Index;
+use tempdir::TempDir;
+
+// base64 md5 of "hi\n"
+const HASH: &str = "dk76iD3aHhHbR2ccSju9ng==";
+
+fn archive() -> TempDir {
+  let directory = TempDir::new("index").unwrap();
+  fs::create_dir(directory.path().join("folder")).unwrap();
+  fs::write(directory.path().join("folder").join("file.txt"), "hi\n").unwrap();
+  directory
+}
+
+#[test]
+fn refresh_finds_files() {
+  let directory = archive();
+  let mut index = Index::load(directory.path());
+
+  assert!(index.names().is_empty());
+  assert!(index.is_stale(Duration::from_secs(60)));
+
+  index.refresh().unwrap();
+
+  assert_eq!(index.names().get(HASH), Some(&"folder".to_string()));
+  assert_eq!(index.folders(), HashSet::from(["folder".to_string()]));
+  assert!(!index.is_stale(Duration::from_secs(60)));
+}
+
+#[test]
+fn refresh_notices_changes() {
+  let directory = archive();
+  let mut index = Index::load(directory.path());
+  index.refresh().unwrap();
+
+  thread::sleep(Duration::from_millis(50));
+  fs::rename(directory.path().join("folder"), directory.path().join("renamed")).unwrap();
+  index.refresh().unwrap();
+
+  assert_eq!(index.names().get(HASH), Some(&"renamed".to_string()));
+  assert_eq!(index.folders(), HashSet::from(["renamed".to_string()]));
+}
+
+#[test]
+fn insert_is_saved() {
+  let directory = archive();
+  let mut index = Index::load(directory.path());
+  index.refresh().unwrap();
+  index.insert("other", "other.txt", "other hash");
+
+  assert_eq!(index.names().get("other hash"), Some(&"other".to_string()));
+
+  index.save().unwrap();
+  let index = Index::load(directory.path());
+
+  assert_eq!(index.names().get(HASH), Some(&"folder".to_string()));
+  assert_eq!(index.names().get("other hash"), Some(&"other".to_string()));
+}
+
+#[test]
+fn unchanged_index_is_not_saved() {
+  let directory = TempDir::new("index").unwrap();
+  let mut index = Index::load(directory.path());
+  index.save().unwrap();
+
+  assert!(!directory.path().join(archive::index::CACHE_PATH).exists());
+}
+
+#[test]
+fn scans_report_progress() {
+  let directory = archive();
+  let mut index = Index::load(directory.path());
+  let reported = Mutex::new(Vec::new());
+
+  let changes = index.scan().run(&|progress| reported.lock().unwrap().push(progress.clone()), &AtomicBool::new(false)).unwrap();
+  index.apply(changes);
+
+  let reported = reported.into_inner().unwrap();
+  let last = reported.last().unwrap();
+
+  assert_eq!(reported[0].files_hashed, 0);
+  assert_eq!((last.files_discovered, last.files_hashed, last.bytes_discovered, last.bytes_hashed), (1, 1, 3, 3));
+  assert_eq!(last.subfolder.as_deref(), Some("folder"));
+
+  // quiet when there's nothing new to hash
+  let reported = Mutex::new(Vec::new());
+  index.scan().run(&|progress| reported.lock().unwrap().push(progress.clone()), &AtomicBool::new(false)).unwrap();
+
+  assert!(reported.into_inner().unwrap().is_empty());
+}
+
+#[test]
+fn cancelled_scans_are_picked_up_later() {
+  let directory = archive();
+  let mut index = Index::load(directory.path());
+
+  let changes = index.scan().run(&|_| {}, &AtomicBool::new(true)).unwrap();
+  assert!(!changes.is_complete());
+  index.apply(changes);
+  index.save().unwrap();
+
+  assert!(index.names().is_empty());
+  assert!(index.is_stale(Duration::from_secs(60)));
+
+  // including by the next run, which starts from what the cancelled one saved
+  let mut index = Index::load(directory.path());
+  index.refresh().unwrap();
+
+  assert_eq!(index.names().get(HASH), Some(&"folder".to_string()));
+}
+
+#[cfg(unix)]
+#[test]
+fn names_that_arent_utf8_are_skipped() {
+  use std::ffi::OsStr;
+  use std::os::unix::ffi::OsStrExt;
+
+  let directory = archive();
+  fs::create_dir(directory.path().join(OsStr::from_bytes(b"\xff"))).unwrap();
+  fs::write(directory.path().join("folder").join(OsStr::from_bytes(b"\xff.txt")), "other\n").unwrap();
+
+  let mut index = Index::load(directory.path());
+  index.refresh().unwrap();
+
+  assert_eq!(index.names().len(), 1);
+  assert_eq!(index.folders(), HashSet::from(["folder".to_string()]));
+}
+
+#[test]
+fn missing_directories_fail_to_refresh() {
+  let directory = TempDir::new("index").unwrap();
+  let mut index = Index::load(&directory.path().join("missing"));
+
+  assert!(index.refresh().is_err());
+}
@@ -25,17 +25,17 @@ let suggestionsAdded = (newSuggestions) => {
 // idempotent; call to change the name associated with a file hash
 let setName = (hash, name, original_filename, filename, url) => {
   if (name.length > 0) {
-    chrome.storage.local.get('original_filename', (items) => {
-      getPort().postMessage(
-        {
-          type: 'set',
-          hash: hash,
-          name: name,
-          filename: (items.original_filename === undefined || items.original_filename) ? original_filename : filename,
-          url: url
-        }
-      )
-    })
+    // the native host picks between the two filenames according to its filename policy
+    getPort().postMessage(
+      {
+        type: 'set',
+        hash: hash,
+        name: name,
+        original_filename: original_filename,
+        filename: filename,
+        url: url
+      }
+    )
   }
 }
 
@@ -1,5 +1,51 @@
+// the native messaging protocol version this extension speaks, see PROTOCOL_VERSION in the native host
+const PROTOCOL_VERSION = 2
+
 let connections = 0
+// each tab's port gets its own request id, so replies can be sent back to the tab that asked
+let nextPortId = 0
 let connection = null
+// the native host's answer to Hello, null until it has replied
+let host = null
+// true while the message being delivered is the reply to Hello
+let handshake = false
+
+let outdated = () => host !== null && host.version < PROTOCOL_VERSION
+
+let outdatedError = {
+  error: 'Please update the native host',
+  detail: 'The installed native host is older than this extension. Download the latest release and run archive.exe from it, or run archive install from a terminal'
+}
+
+// what the user can do about errors from the native host, by error code
+let errorDetails = {
+  directory_missing: 'Go to the archive extension options page to set an archive directory',
+  permission_denied: 'Check that the archive directory can be written to',
+  disk_full: 'Free up some disk space and try again',
+  http: 'The image may have been deleted, try again later',
+  network: 'Check your internet connection and try again',
+  hash_mismatch: 'The download didn\'t match the image on the page, try again later',
+  panic: 'This is a bug in the native host, please report it'
+}
+
+// newer hosts answer Hello with its id, possibly after replies to messages sent after it
+// hosts from before the handshake existed don't echo ids, and reply to Hello first with an error, which we treat as version 1
+let connect = () => {
+  connection = chrome.runtime.connectNative('com.dagwaging.archive')
+  host = null
+
+  connection.onMessage.addListener((message) => {
+    handshake = host === null && (message.id === 'hello' || message.id === undefined)
+
+    if (handshake) {
+      host = message.type == 'hello' ? message : { version: 1, capabilities: {} }
+      console.log('Native host', host)
+    }
+  })
+
+  // this is the connection that stays open, so it's the one that picks up downloads left unfinished
+  connection.postMessage({ "Hello": { version: PROTOCOL_VERSION, resume_jobs: true }, id: 'hello' })
+}
 
 chrome.storage.onChanged.addListener((changes, areaName) => {
   if (areaName == 'local' && changes.directory) {
@@ -7,11 +53,12 @@ chrome.storage.onChanged.addListener((changes, areaName) => {
 
     if (changes.directory.newValue) {
       if (!connection) {
-        connection = chrome.runtime.connectNative('com.dagwaging.archive')
+        connect()
       }
 
+      // the options page only mirrors the archive directory here to let us know it changed, the native host owns the setting
       // our cache is probably invalid, we should eagerly rehash everything
-      connection.postMessage({ "Get": { directory: changes.directory.newValue, hashes: [] } })
+      connection.postMessage({ "Get": { hashes: [] }, id: 'rescan' })
     }
   }
 })
@@ -19,15 +66,31 @@ chrome.storage.onChanged.addListener((changes, areaName) => {
 chrome.runtime.onConnect.addListener((port) => {
   if (!connection) {
     // TODO: if chrome is unable to communicate with the native host, this will silently fail
-    connection = chrome.runtime.connectNative('com.dagwaging.archive')
+    connect()
   }
 
+  let portId = nextPortId++
+
   let listener = (message, _) => {
     let error = chrome.runtime.lastError
     if (error) {
       port.postMessage({ error: error })
     }
-    else {
+    else if (handshake) {
+      if (outdated()) {
+        port.postMessage(outdatedError)
+      }
+    }
+    else if (message.error && outdated()) {
+      // an old host fails on messages it doesn't understand, which isn't useful to show
+      port.postMessage(outdatedError)
+    }
+    // suggestions and progress describe the whole archive, so every tab and the options page can use them no matter who asked
+    else if (message.id === undefined || message.id === portId || message.type == 'suggestions' || message.type == 'progress') {
+      if (message.type == 'error' && message.detail === undefined) {
+        message.detail = errorDetails[message.code] || message.error
+      }
+
       port.postMessage(message)
     }
   }
@@ -40,36 +103,43 @@ chrome.runtime.onConnect.addListener((port) => {
   port.onMessage.addListener((message, port) => {
     console.log('Message received', message)
 
-    chrome.storage.local.get('directory', (items) => {
-      if (!items.directory) {
-        port.postMessage({ error: 'No directory set', detail: 'Go to the archive extension options page to set an archive directory' })
-        return
-      }
+    if (outdated()) {
+      port.postMessage(outdatedError)
+      return
+    }
 
-      try {
-        switch (message.type) {
-          case 'get':
-            connection.postMessage({ "Get": { directory: items.directory, hashes: message.hashes } })
-            break
-          case 'set':
-            connection.postMessage({
-              "Set": {
-                directory: items.directory,
-                url: message.url,
-                hash: message.hash,
-                name: message.name,
-                filename: message.filename
-              }
-            })
-            break
-        }
-      }
-      catch (error) {
-        connection = null
-        port.postMessage({ error: 'Extension not configured', detail: 'Run archive.exe to configure the extension' })
-        return
+    try {
+      switch (message.type) {
+        case 'get':
+          connection.postMessage({ "Get": { hashes: message.hashes }, id: portId })
+          break
+        case 'set':
+          connection.postMessage({
+            "Set": {
+              url: message.url,
+              hash: message.hash,
+              name: message.name,
+              original_filename: message.original_filename,
+              filename: message.filename
+            },
+            id: portId
+          })
+          break
+        // hosts that can handle it say so with the batch capability
+        case 'set_many':
+          connection.postMessage({ "SetMany": { items: message.items }, id: portId })
+          break
+        // answered with progress and what was archived before the job itself, so it can't go through sendNativeMessage
+        case 'retry_job':
+          connection.postMessage({ "RetryJob": { directory: message.directory, id: message.id }, id: portId })
+          break
       }
-    })
+    }
+    catch (error) {
+      connection = null
+      port.postMessage({ error: 'Extension not configured', detail: 'Run archive.exe to configure the extension, or run archive doctor from a terminal to find out what is wrong' })
+      return
+    }
   })
 
   port.onDisconnect.addListener((port) => {
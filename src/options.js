@@ -1,51 +1,126 @@
-document.getElementById('directory').addEventListener('click', (event) => {
-  chrome.runtime.sendNativeMessage(
-    'com.dagwaging.archive',
-    {
-      "Pick": null
-    },
-    (response) => {
-      let error = chrome.runtime.lastError
-      if (error) {
-        console.log({ error: error })
-      }
-      else {
-        chrome.storage.local.set({ directory: response.msg }, () => {
-          let error = chrome.runtime.lastError
-          if (error) {
-            console.log(error)
-          }
-        })
-      }
-    }
-  )
-})
-
-document.getElementById('original_filename').addEventListener('change', (event) => {
-  chrome.storage.local.set({ original_filename: event.target.checked }, () => {
-    let error = chrome.runtime.lastError
-    if (error) {
-      console.log(error)
-    }
-  })
-})
-
-chrome.storage.onChanged.addListener((changes, areaName) => {
-  if (areaName == 'local') {
-    if (changes.directory !== undefined) {
-      document.getElementById('label').innerText = changes.directory.newValue
-    }
-
-    if (changes.original_filename !== undefined) {
-      document.getElementById('original_filename').checked = changes.original_filename.newValue
-    }
-  }
-})
-
-chrome.storage.local.get(['directory', 'original_filename'], (items) => {
-  if (items.directory !== undefined) {
-    document.getElementById('label').innerText = items.directory
-  }
-
-  document.getElementById('original_filename').checked = items.original_filename === undefined || items.original_filename
-})
+// settings live in the native host's config file so other tools on the machine see the same ones
+let sendNativeMessage = (message, callback) => {
+  chrome.runtime.sendNativeMessage('com.dagwaging.archive', message, (response) => {
+    let error = chrome.runtime.lastError
+    if (error) {
+      console.log({ error: error })
+      document.getElementById('label').innerText = error.message
+    }
+    else if (response.type == 'error') {
+      document.getElementById('label').innerText = response.error
+    }
+    else {
+      callback(response)
+    }
+  })
+}
+
+let showSettings = (settings) => {
+  document.getElementById('label').innerText = settings.roots.length > 0 ? settings.roots[0] : ''
+  document.getElementById('original_filename').checked = settings.filename == 'original'
+}
+
+// reads the settings fresh each time so changes made outside the browser aren't overwritten
+let updateSettings = (update) => {
+  sendNativeMessage({ "GetConfig": null }, (response) => {
+    sendNativeMessage({ "SetConfig": update(response.msg) }, (response) => {
+      showSettings(response.msg)
+
+      // lets the background page and open tabs know to look at the new directory
+      chrome.storage.local.set({ directory: response.msg.roots[0] }, () => {
+        let error = chrome.runtime.lastError
+        if (error) {
+          console.log(error)
+        }
+      })
+    })
+  })
+}
+
+document.getElementById('directory').addEventListener('click', (event) => {
+  sendNativeMessage({ "Pick": null }, (response) => {
+    if (response.status == 'invalid') {
+      document.getElementById('label').innerText = `${response.msg} can't be used, pick a folder with a plain text path`
+    }
+    else if (response.status == 'selected') {
+      updateSettings((settings) => {
+        settings.roots = [response.msg, ...settings.roots.filter(root => root != response.msg)]
+        return settings
+      })
+    }
+  })
+})
+
+document.getElementById('original_filename').addEventListener('change', (event) => {
+  updateSettings((settings) => {
+    settings.filename = event.target.checked ? 'original' : 'server'
+    return settings
+  })
+})
+
+// downloads the native host has journaled because they haven't finished, including ones that failed and are waiting to be retried
+let showJobs = () => {
+  sendNativeMessage({ "ListJobs": {} }, (response) => {
+    document.getElementById('jobs').replaceChildren(...Object.entries(response.msg).flatMap(([directory, jobs]) => jobs.map(job => {
+      let row = document.createElement('div')
+      let label = document.createElement('span')
+      label.innerText = `${job.name}/${job.filename}: ${job.state}${job.error ? ` (${job.error})` : ''} `
+
+      // a retry is only answered once it's done, so the list is shown again straight away to say it's running
+      let retry = document.createElement('button')
+      retry.innerText = 'Retry'
+      retry.disabled = job.state == 'running'
+      retry.addEventListener('click', () => {
+        background.postMessage({ type: 'retry_job', directory: directory, id: job.id })
+        setTimeout(showJobs, 500)
+      })
+
+      let drop = document.createElement('button')
+      drop.innerText = 'Drop'
+      drop.addEventListener('click', () => sendNativeMessage({ "DropJob": { directory: directory, id: job.id } }, () => showJobs()))
+
+      row.append(label, retry, drop)
+      return row
+    })))
+  })
+}
+
+// hosts from before the handshake existed reply to Hello with an error, and don't have settings either
+chrome.runtime.sendNativeMessage('com.dagwaging.archive', { "Hello": { version: 2 } }, (response) => {
+  if (!chrome.runtime.lastError && (response.type != 'hello' || response.version < 2)) {
+    document.getElementById('label').innerText = 'Please update the native host by running archive.exe from the latest release'
+  }
+  else {
+    sendNativeMessage({ "GetConfig": null }, (response) => showSettings(response.msg))
+
+    if (response.capabilities && response.capabilities.jobs) {
+      showJobs()
+    }
+  }
+})
+
+// the background page passes on progress while the native host indexes the archive directory, including after picking a new one
+let showProgress = (progress) => {
+  let done = progress.files_hashed == progress.files_discovered
+  let eta = progress.eta_seconds != null && !done ? `, about ${progress.eta_seconds}s left` : ''
+
+  document.getElementById('indexing').hidden = done
+  document.getElementById('progress').max = progress.bytes_discovered || 1
+  document.getElementById('progress').value = progress.bytes_hashed
+  document.getElementById('progress_label').innerText = `Indexing ${progress.subfolder || progress.directory}: ${progress.files_hashed} of ${progress.files_discovered} files${eta}`
+}
+
+// also answers retries, which go through the background page's connection so the host stays up until the job's done
+let background = chrome.runtime.connect()
+
+background.onMessage.addListener((message) => {
+  if (message.type == 'progress') {
+    showProgress(message)
+  }
+  else if (message.type == 'job' && !message.more) {
+    showJobs()
+  }
+  else if (message.type == 'error' || message.error) {
+    document.getElementById('label').innerText = message.detail || message.error.message || message.error
+  }
+})